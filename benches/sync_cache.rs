@@ -0,0 +1,76 @@
+//! Measures how lookups of already-cached users scale across threads,
+//! comparing a `SyncUsersCache` shared through an `Arc` with a `UsersCache`
+//! shared through an `Arc<Mutex<_>>`.
+//!
+//! Run it with `cargo bench --bench sync_cache`. Each line shows the total
+//! number of lookups per second for that many threads; for the sync cache,
+//! this should keep rising as threads are added, whereas the mutex-wrapped
+//! cache stays flat (or gets slower) as the threads fight over the lock.
+
+extern crate users;
+use users::{Users, UsersCache, SyncUsersCache, get_current_uid};
+
+use std::sync::{Arc, Barrier, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const LOOKUPS_PER_THREAD: u32 = 200_000;
+const THREAD_COUNTS: &[usize] = &[1, 2, 4, 8];
+
+
+fn main() {
+    let uid = get_current_uid();
+
+    println!("{:>8}  {:>20}  {:>20}", "threads", "Mutex<UsersCache>", "SyncUsersCache");
+    for &threads in THREAD_COUNTS {
+        let mutex_cache = Arc::new(Mutex::new(UsersCache::new()));
+        let mutex_time = run(threads, move || {
+            let cache = Arc::clone(&mutex_cache);
+            move || { cache.lock().unwrap().get_user_by_uid(uid); }
+        });
+
+        let sync_cache = Arc::new(SyncUsersCache::new());
+        let sync_time = run(threads, move || {
+            let cache = Arc::clone(&sync_cache);
+            move || { cache.get_user_by_uid(uid); }
+        });
+
+        println!("{:>8}  {:>14} ops/s  {:>14} ops/s", threads,
+                 ops_per_second(threads, mutex_time), ops_per_second(threads, sync_time));
+    }
+}
+
+/// Runs the lookup produced by `make_lookup` on the given number of threads
+/// at once, after warming the cache, and returns how long they took.
+fn run<F, L>(threads: usize, make_lookup: F) -> Duration
+where F: Fn() -> L,
+      L: Fn() + Send + 'static,
+{
+    make_lookup()();
+
+    let barrier = Arc::new(Barrier::new(threads + 1));
+    let handles: Vec<_> = (0 .. threads).map(|_| {
+        let lookup = make_lookup();
+        let barrier = Arc::clone(&barrier);
+        thread::spawn(move || {
+            barrier.wait();
+            for _ in 0 .. LOOKUPS_PER_THREAD {
+                lookup();
+            }
+        })
+    }).collect();
+
+    barrier.wait();
+    let start = Instant::now();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    start.elapsed()
+}
+
+fn ops_per_second(threads: usize, time: Duration) -> u64 {
+    let lookups = u64::from(LOOKUPS_PER_THREAD) * threads as u64;
+    let nanos = time.as_secs() * 1_000_000_000 + u64::from(time.subsec_nanos());
+    lookups * 1_000_000_000 / nanos.max(1)
+}
+
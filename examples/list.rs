@@ -1,5 +1,5 @@
 extern crate users;
-use users::{User, all_users};
+use users::{User, list_all_users};
 
 extern crate env_logger;
 
@@ -7,8 +7,8 @@ extern crate env_logger;
 fn main() {
     env_logger::init();
 
-    let mut users: Vec<User> = unsafe { all_users() }.collect();
-    users.sort_by(|a, b| a.uid().cmp(&b.uid()));
+    let mut users: Vec<User> = list_all_users().expect("Failed to list the users");
+    users.sort_by_key(|u| u.uid());
 
     for user in users {
         println!("User {} has name {}", user.uid(), user.name().to_string_lossy());
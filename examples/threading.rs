@@ -10,6 +10,9 @@
 //!
 //! Then, afterwards, it retrieves references to the users that had been
 //! cached earlier.
+//!
+//! If you don’t want every lookup to go through the one lock, use a
+//! `SyncUsersCache` instead, which can be shared with just an `Arc`.
 
 // For extra fun, try uncommenting some of the lines of code below, making
 // the code try to access the users cache *without* a Mutex, and see it
@@ -29,13 +29,16 @@
 //! best bet is to check for them yourself before passing strings into any
 //! functions.
 
+use std::collections::HashSet;
+use std::error::Error;
 use std::ffi::{CStr, CString, OsStr, OsString};
 use std::fmt;
 use std::mem;
 use std::io;
 use std::os::unix::ffi::OsStrExt;
 use std::ptr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::sync::atomic::{AtomicPtr, Ordering};
 
 #[cfg(feature = "logging")]
 extern crate log;
@@ -106,7 +109,7 @@ impl User {
     /// assert_eq!(user.name(), OsStr::new("stevedore"));
     /// ```
     pub fn name(&self) -> &OsStr {
-        &*self.name_arc
+        &self.name_arc
     }
 
     /// Returns the ID of this user’s primary group.
@@ -220,7 +223,7 @@ impl Group {
     /// assert_eq!(group.name(), OsStr::new("database"));
     /// ```
     pub fn name(&self) -> &OsStr {
-        &*self.name_arc
+        &self.name_arc
     }
 }
 
@@ -240,13 +243,97 @@ impl fmt::Debug for Group {
 }
 
 
+/// An error that occurred while looking up a user or group, as opposed to
+/// the user or group simply not existing.
+///
+/// It records the error number that the C library returned, along with the
+/// name of the function that returned it.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct LookupError {
+    function: &'static str,
+    errno: c_int,
+}
+
+impl LookupError {
+
+    /// Creates an error as if the given libc function had returned the
+    /// given error number, for mock implementations of `Users` and `Groups`.
+    pub fn new(function: &'static str, errno: i32) -> Self {
+        Self { function, errno }
+    }
+
+    /// Returns the name of the libc function that failed, such as
+    /// `getpwuid_r`.
+    pub fn function(&self) -> &'static str {
+        self.function
+    }
+
+    /// Returns the error number returned by the failed call.
+    pub fn raw_os_error(&self) -> i32 {
+        self.errno
+    }
+}
+
+impl fmt::Debug for LookupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LookupError")
+         .field("function", &self.function)
+         .field("errno", &self.errno)
+         .field("message", &io::Error::from_raw_os_error(self.errno).to_string())
+         .finish()
+    }
+}
+
+impl fmt::Display for LookupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} failed: {}", self.function, io::Error::from_raw_os_error(self.errno))
+    }
+}
+
+impl Error for LookupError {}
+
+impl From<LookupError> for io::Error {
+    fn from(error: LookupError) -> Self {
+        Self::from_raw_os_error(error.errno)
+    }
+}
+
+/// Checks the return value of one of the `get*_r` functions, treating the
+/// error numbers that some C libraries use to mean “not found” as success.
+pub(crate) fn check_lookup_result(function: &'static str, r: c_int) -> Result<(), LookupError> {
+    match r {
+        0 | libc::ENOENT | libc::ESRCH | libc::EBADF | libc::EPERM => Ok(()),
+        errno => {
+            #[cfg(feature = "logging")]
+            trace!("{} failed with error {}", function, errno);
+
+            Err(LookupError { function, errno })
+        }
+    }
+}
+
+/// Doubles the size of the buffer passed to one of the `get*_r` functions
+/// after it returned `ERANGE`, failing if it can’t grow any further.
+pub(crate) fn grow_buffer(function: &'static str, buf: &mut Vec<c_char>) -> Result<(), LookupError> {
+    match buf.len().checked_mul(2) {
+        Some(newsize) => {
+            buf.resize(newsize, 0);
+            Ok(())
+        }
+        None => {
+            Err(LookupError { function, errno: libc::ERANGE })
+        }
+    }
+}
+
+
 /// Reads data from a `*char` field in `c_passwd` or `g_group`. The return
 /// type will be an `Arc<OsStr>` if the text is meant to be shared in a cache,
 /// or a plain `OsString` if it’s not.
 ///
 /// The underlying buffer is managed by the C library, not by us, so we *need*
 /// to move data out of it before the next user gets read.
-unsafe fn from_raw_buf<'a, T>(p: *const c_char) -> T
+pub(crate) unsafe fn from_raw_buf<'a, T>(p: *const c_char) -> T
 where T: From<&'a OsStr>
 {
     T::from(OsStr::from_bytes(CStr::from_ptr(p).to_bytes()))
@@ -309,6 +396,10 @@ unsafe fn members(groups: *mut *mut c_char) -> Vec<OsString> {
 /// Searches for a `User` with the given ID in the system’s user database.
 /// Returns it if one is found, otherwise returns `None`.
 ///
+/// This function returns `None` when the lookup itself fails, too. Use
+/// [`try_get_user_by_uid`](fn.try_get_user_by_uid.html) to tell the two
+/// cases apart.
+///
 /// # libc functions used
 ///
 /// - [`getpwuid_r`](https://docs.rs/libc/*/libc/fn.getpwuid_r.html)
@@ -324,6 +415,35 @@ unsafe fn members(groups: *mut *mut c_char) -> Vec<OsString> {
 /// }
 /// ```
 pub fn get_user_by_uid(uid: uid_t) -> Option<User> {
+    try_get_user_by_uid(uid).unwrap_or(None)
+}
+
+/// Searches for a `User` with the given ID in the system’s user database.
+/// Returns `Ok(Some(user))` if one is found, and `Ok(None)` if there is no
+/// such user.
+///
+/// # libc functions used
+///
+/// - [`getpwuid_r`](https://docs.rs/libc/*/libc/fn.getpwuid_r.html)
+///
+/// # Errors
+///
+/// This function will return `Err` when the `getpwuid_r` call fails for a
+/// reason other than the user not existing, such as an I/O error or a
+/// broken NSS backend.
+///
+/// # Examples
+///
+/// ```
+/// use users::try_get_user_by_uid;
+///
+/// match try_get_user_by_uid(501) {
+///     Ok(Some(user)) => println!("Found user {:?}", user.name()),
+///     Ok(None)       => println!("User not found"),
+///     Err(e)         => println!("Lookup failed: {}", e),
+/// }
+/// ```
+pub fn try_get_user_by_uid(uid: uid_t) -> Result<Option<User>, LookupError> {
     let mut passwd = unsafe { mem::zeroed::<c_passwd>() };
     let mut buf = vec![0; 2048];
     let mut result = ptr::null_mut::<c_passwd>();
@@ -337,31 +457,34 @@ pub fn get_user_by_uid(uid: uid_t) -> Option<User> {
         };
 
         if r != libc::ERANGE {
+            check_lookup_result("getpwuid_r", r)?;
             break;
         }
 
-        let newsize = buf.len().checked_mul(2)?;
-        buf.resize(newsize, 0);
+        grow_buffer("getpwuid_r", &mut buf)?;
     }
 
     if result.is_null() {
-        // There is no such user, or an error has occurred.
-        // errno gets set if there’s an error.
-        return None;
+        // There is no such user.
+        return Ok(None);
     }
 
     if result != &mut passwd {
         // The result of getpwuid_r should be its input passwd.
-        return None;
+        return Ok(None);
     }
 
     let user = unsafe { passwd_to_user(result.read()) };
-    Some(user)
+    Ok(Some(user))
 }
 
 /// Searches for a `User` with the given username in the system’s user database.
 /// Returns it if one is found, otherwise returns `None`.
 ///
+/// This function returns `None` when the lookup itself fails, too. Use
+/// [`try_get_user_by_name`](fn.try_get_user_by_name.html) to tell the two
+/// cases apart.
+///
 /// # libc functions used
 ///
 /// - [`getpwnam_r`](https://docs.rs/libc/*/libc/fn.getpwnam_r.html)
@@ -377,12 +500,41 @@ pub fn get_user_by_uid(uid: uid_t) -> Option<User> {
 /// }
 /// ```
 pub fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(username: &S) -> Option<User> {
+    try_get_user_by_name(username).unwrap_or(None)
+}
+
+/// Searches for a `User` with the given username in the system’s user database.
+/// Returns `Ok(Some(user))` if one is found, and `Ok(None)` if there is no
+/// such user.
+///
+/// # libc functions used
+///
+/// - [`getpwnam_r`](https://docs.rs/libc/*/libc/fn.getpwnam_r.html)
+///
+/// # Errors
+///
+/// This function will return `Err` when the `getpwnam_r` call fails for a
+/// reason other than the user not existing, such as an I/O error or a
+/// broken NSS backend.
+///
+/// # Examples
+///
+/// ```
+/// use users::try_get_user_by_name;
+///
+/// match try_get_user_by_name("stevedore") {
+///     Ok(Some(user)) => println!("Found user #{}", user.uid()),
+///     Ok(None)       => println!("User not found"),
+///     Err(e)         => println!("Lookup failed: {}", e),
+/// }
+/// ```
+pub fn try_get_user_by_name<S: AsRef<OsStr> + ?Sized>(username: &S) -> Result<Option<User>, LookupError> {
     let username = match CString::new(username.as_ref().as_bytes()) {
         Ok(u)  => u,
         Err(_) => {
             // The username that was passed in contained a null character,
             // which will match no usernames.
-            return None;
+            return Ok(None);
         }
     };
 
@@ -399,31 +551,34 @@ pub fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(username: &S) -> Option<User>
         };
 
         if r != libc::ERANGE {
+            check_lookup_result("getpwnam_r", r)?;
             break;
         }
 
-        let newsize = buf.len().checked_mul(2)?;
-        buf.resize(newsize, 0);
+        grow_buffer("getpwnam_r", &mut buf)?;
     }
 
     if result.is_null() {
-        // There is no such user, or an error has occurred.
-        // errno gets set if there’s an error.
-        return None;
+        // There is no such user.
+        return Ok(None);
     }
 
     if result != &mut passwd {
         // The result of getpwnam_r should be its input passwd.
-        return None;
+        return Ok(None);
     }
 
     let user = unsafe { passwd_to_user(result.read()) };
-    Some(user)
+    Ok(Some(user))
 }
 
 /// Searches for a `Group` with the given ID in the system’s group database.
 /// Returns it if one is found, otherwise returns `None`.
 ///
+/// This function returns `None` when the lookup itself fails, too. Use
+/// [`try_get_group_by_gid`](fn.try_get_group_by_gid.html) to tell the two
+/// cases apart.
+///
 /// # libc functions used
 ///
 /// - [`getgrgid_r`](https://docs.rs/libc/*/libc/fn.getgrgid_r.html)
@@ -439,6 +594,35 @@ pub fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(username: &S) -> Option<User>
 /// }
 /// ```
 pub fn get_group_by_gid(gid: gid_t) -> Option<Group> {
+    try_get_group_by_gid(gid).unwrap_or(None)
+}
+
+/// Searches for a `Group` with the given ID in the system’s group database.
+/// Returns `Ok(Some(group))` if one is found, and `Ok(None)` if there is no
+/// such group.
+///
+/// # libc functions used
+///
+/// - [`getgrgid_r`](https://docs.rs/libc/*/libc/fn.getgrgid_r.html)
+///
+/// # Errors
+///
+/// This function will return `Err` when the `getgrgid_r` call fails for a
+/// reason other than the group not existing, such as an I/O error or a
+/// broken NSS backend.
+///
+/// # Examples
+///
+/// ```
+/// use users::try_get_group_by_gid;
+///
+/// match try_get_group_by_gid(102) {
+///     Ok(Some(group)) => println!("Found group {:?}", group.name()),
+///     Ok(None)        => println!("Group not found"),
+///     Err(e)          => println!("Lookup failed: {}", e),
+/// }
+/// ```
+pub fn try_get_group_by_gid(gid: gid_t) -> Result<Option<Group>, LookupError> {
     let mut passwd = unsafe { mem::zeroed::<c_group>() };
     let mut buf = vec![0; 2048];
     let mut result = ptr::null_mut::<c_group>();
@@ -452,31 +636,34 @@ pub fn get_group_by_gid(gid: gid_t) -> Option<Group> {
         };
 
         if r != libc::ERANGE {
+            check_lookup_result("getgrgid_r", r)?;
             break;
         }
 
-        let newsize = buf.len().checked_mul(2)?;
-        buf.resize(newsize, 0);
+        grow_buffer("getgrgid_r", &mut buf)?;
     }
 
     if result.is_null() {
-        // There is no such group, or an error has occurred.
-        // errno gets set if there’s an error.
-        return None;
+        // There is no such group.
+        return Ok(None);
     }
 
     if result != &mut passwd {
         // The result of getgrgid_r should be its input struct.
-        return None;
+        return Ok(None);
     }
 
     let group = unsafe { struct_to_group(result.read()) };
-    Some(group)
+    Ok(Some(group))
 }
 
 /// Searches for a `Group` with the given group name in the system’s group database.
 /// Returns it if one is found, otherwise returns `None`.
 ///
+/// This function returns `None` when the lookup itself fails, too. Use
+/// [`try_get_group_by_name`](fn.try_get_group_by_name.html) to tell the two
+/// cases apart.
+///
 /// # libc functions used
 ///
 /// - [`getgrnam_r`](https://docs.rs/libc/*/libc/fn.getgrnam_r.html)
@@ -492,12 +679,41 @@ pub fn get_group_by_gid(gid: gid_t) -> Option<Group> {
 /// }
 /// ```
 pub fn get_group_by_name<S: AsRef<OsStr> + ?Sized>(groupname: &S) -> Option<Group> {
+    try_get_group_by_name(groupname).unwrap_or(None)
+}
+
+/// Searches for a `Group` with the given group name in the system’s group database.
+/// Returns `Ok(Some(group))` if one is found, and `Ok(None)` if there is no
+/// such group.
+///
+/// # libc functions used
+///
+/// - [`getgrnam_r`](https://docs.rs/libc/*/libc/fn.getgrnam_r.html)
+///
+/// # Errors
+///
+/// This function will return `Err` when the `getgrnam_r` call fails for a
+/// reason other than the group not existing, such as an I/O error or a
+/// broken NSS backend.
+///
+/// # Examples
+///
+/// ```
+/// use users::try_get_group_by_name;
+///
+/// match try_get_group_by_name("db-access") {
+///     Ok(Some(group)) => println!("Found group #{}", group.gid()),
+///     Ok(None)        => println!("Group not found"),
+///     Err(e)          => println!("Lookup failed: {}", e),
+/// }
+/// ```
+pub fn try_get_group_by_name<S: AsRef<OsStr> + ?Sized>(groupname: &S) -> Result<Option<Group>, LookupError> {
     let groupname = match CString::new(groupname.as_ref().as_bytes()) {
         Ok(u)  => u,
         Err(_) => {
             // The groupname that was passed in contained a null character,
             // which will match no usernames.
-            return None;
+            return Ok(None);
         }
     };
 
@@ -514,26 +730,25 @@ pub fn get_group_by_name<S: AsRef<OsStr> + ?Sized>(groupname: &S) -> Option<Grou
         };
 
         if r != libc::ERANGE {
+            check_lookup_result("getgrnam_r", r)?;
             break;
         }
 
-        let newsize = buf.len().checked_mul(2)?;
-        buf.resize(newsize, 0);
+        grow_buffer("getgrnam_r", &mut buf)?;
     }
 
     if result.is_null() {
-        // There is no such group, or an error has occurred.
-        // errno gets set if there’s an error.
-        return None;
+        // There is no such group.
+        return Ok(None);
     }
 
     if result != &mut group {
         // The result of getgrnam_r should be its input struct.
-        return None;
+        return Ok(None);
     }
 
     let group = unsafe { struct_to_group(result.read()) };
-    Some(group)
+    Ok(Some(group))
 }
 
 /// Returns the user ID for the user running the process.
@@ -715,6 +930,103 @@ pub fn get_effective_groupname() -> Option<OsString> {
     Some(OsString::from(&*group.name_arc))
 }
 
+/// Returns the real, effective, and saved user IDs of the running process,
+/// in that order.
+///
+/// The saved set-user-ID is the one a process can switch its effective user
+/// back to, so a process whose saved user ID is still 0 can regain root.
+///
+/// # libc functions used
+///
+/// - [`getresuid`](https://docs.rs/libc/*/libc/fn.getresuid.html)
+///
+/// # Examples
+///
+/// ```
+/// use users::get_resuid;
+///
+/// let (real, effective, saved) = get_resuid();
+/// println!("Real {}, effective {}, saved {}", real, effective, saved);
+/// ```
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd", target_os = "dragonfly", target_os = "openbsd"))]
+pub fn get_resuid() -> (uid_t, uid_t, uid_t) {
+    let (mut ruid, mut euid, mut suid) = (0, 0, 0);
+
+    #[cfg(feature = "logging")]
+    trace!("Running getresuid");
+
+    match unsafe { libc::getresuid(&mut ruid, &mut euid, &mut suid) } {
+         0 => (ruid, euid, suid),
+         n => unreachable!("getresuid returned {}", n)
+    }
+}
+
+/// Returns the real, effective, and saved group IDs of the running process,
+/// in that order.
+///
+/// # libc functions used
+///
+/// - [`getresgid`](https://docs.rs/libc/*/libc/fn.getresgid.html)
+///
+/// # Examples
+///
+/// ```
+/// use users::get_resgid;
+///
+/// let (real, effective, saved) = get_resgid();
+/// println!("Real {}, effective {}, saved {}", real, effective, saved);
+/// ```
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd", target_os = "dragonfly", target_os = "openbsd"))]
+pub fn get_resgid() -> (gid_t, gid_t, gid_t) {
+    let (mut rgid, mut egid, mut sgid) = (0, 0, 0);
+
+    #[cfg(feature = "logging")]
+    trace!("Running getresgid");
+
+    match unsafe { libc::getresgid(&mut rgid, &mut egid, &mut sgid) } {
+         0 => (rgid, egid, sgid),
+         n => unreachable!("getresgid returned {}", n)
+    }
+}
+
+/// Returns the saved set-user-ID of the running process.
+///
+/// # libc functions used
+///
+/// - [`getresuid`](https://docs.rs/libc/*/libc/fn.getresuid.html)
+///
+/// # Examples
+///
+/// ```
+/// use users::get_saved_uid;
+///
+/// if get_saved_uid() == 0 {
+///     println!("This process can still become root");
+/// }
+/// ```
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd", target_os = "dragonfly", target_os = "openbsd"))]
+pub fn get_saved_uid() -> uid_t {
+    get_resuid().2
+}
+
+/// Returns the saved set-group-ID of the running process.
+///
+/// # libc functions used
+///
+/// - [`getresgid`](https://docs.rs/libc/*/libc/fn.getresgid.html)
+///
+/// # Examples
+///
+/// ```
+/// use users::get_saved_gid;
+///
+/// println!("The saved group ID is {}", get_saved_gid());
+/// ```
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd", target_os = "dragonfly", target_os = "openbsd"))]
+pub fn get_saved_gid() -> gid_t {
+    get_resgid().2
+}
+
 /// Returns the group access list for the current process.
 ///
 /// # libc functions used
@@ -749,6 +1061,9 @@ pub fn group_access_list() -> io::Result<Vec<Group>> {
         Err(io::Error::last_os_error())
     }
     else {
+        // Only the first `res` entries were filled in.
+        buff.truncate(res as usize);
+
         let mut groups = buff.into_iter()
                              .filter_map(get_group_by_gid)
                              .collect::<Vec<_>>();
@@ -773,45 +1088,132 @@ pub fn group_access_list() -> io::Result<Vec<Group>> {
 /// }
 /// ```
 pub fn get_user_groups<S: AsRef<OsStr> + ?Sized>(username: &S, gid: gid_t) -> Option<Vec<Group>> {
+    #[cfg(feature = "logging")]
+    trace!("Running getgrouplist for user {:?} and group #{}", username.as_ref(), gid);
+
+    let gids = remove_duplicates(user_group_ids(username.as_ref(), gid)?);
+
+    let groups = gids.into_iter()
+                     .filter_map(get_group_by_gid)
+                     .collect::<Vec<_>>();
+    Some(groups)
+}
+
+/// Returns the IDs of the groups that the user with the given name is a
+/// member of, along with the given primary group, growing the buffer passed
+/// to `getgrouplist` until they all fit. Returns `None` if the name contains
+/// a null character or the list can’t be read.
+pub(crate) fn user_group_ids(username: &OsStr, gid: gid_t) -> Option<Vec<gid_t>> {
+    const MAX_GROUPS: usize = 1 << 20;
+
+    let name = CString::new(username.as_bytes()).ok()?;
+
     // MacOS uses i32 instead of gid_t in getgrouplist for unknown reasons
     #[cfg(all(unix, target_os="macos"))]
     let mut buff: Vec<i32> = vec![0; 1024];
     #[cfg(all(unix, not(target_os="macos")))]
     let mut buff: Vec<gid_t> = vec![0; 1024];
 
-    let name = CString::new(username.as_ref().as_bytes()).unwrap();
-    let mut count = buff.len() as c_int;
+    loop {
+        let mut count = buff.len() as c_int;
 
-    #[cfg(feature = "logging")]
-    trace!("Running getgrouplist for user {:?} and group #{}", username.as_ref(), gid);
+        #[cfg(all(unix, target_os="macos"))]
+        let res = unsafe {
+            libc::getgrouplist(name.as_ptr(), gid as i32, buff.as_mut_ptr(), &mut count)
+        };
 
-    // MacOS uses i32 instead of gid_t in getgrouplist for unknown reasons
-    #[cfg(all(unix, target_os="macos"))]
-    let res = unsafe {
-        libc::getgrouplist(name.as_ptr(), gid as i32, buff.as_mut_ptr(), &mut count)
-    };
+        #[cfg(all(unix, not(target_os="macos")))]
+        let res = unsafe {
+            libc::getgrouplist(name.as_ptr(), gid, buff.as_mut_ptr(), &mut count)
+        };
 
-    #[cfg(all(unix, not(target_os="macos")))]
-    let res = unsafe {
-        libc::getgrouplist(name.as_ptr(), gid, buff.as_mut_ptr(), &mut count)
-    };
+        if res >= 0 {
+            // Only the first `count` entries were filled in.
+            buff.truncate(count.max(0) as usize);
+            break;
+        }
 
-    if res < 0 {
-        None
+        // Some platforms say how many groups there are, others don’t.
+        let wanted = (count.max(0) as usize).max(buff.len() * 2);
+        if wanted > MAX_GROUPS {
+            return None;
+        }
+
+        buff.resize(wanted, 0);
     }
-    else {
-        buff.dedup();
-        buff.into_iter()
-            .filter_map(|i| get_group_by_gid(i as gid_t))
-            .collect::<Vec<_>>()
-            .into()
+
+    // The cast is only needed on macOS, where the buffer holds `i32`s
+    #[allow(trivial_numeric_casts)]
+    let gids = buff.into_iter().map(|i| i as gid_t).collect();
+    Some(gids)
+}
+
+/// Removes every repeated group ID from a membership list, keeping the
+/// first occurrence of each, so the primary group stays at the front. A
+/// group can appear more than once when it’s listed in several places.
+pub(crate) fn remove_duplicates(mut gids: Vec<gid_t>) -> Vec<gid_t> {
+    let mut seen = HashSet::new();
+    gids.retain(|gid| seen.insert(*gid));
+    gids
+}
+
+
+
+/// A lock that can be kept in a `static`. `Mutex::new` can only be used
+/// there from Rust 1.63 on, so the mutex is allocated the first time it’s
+/// taken instead, and then never freed.
+pub(crate) struct StaticLock(AtomicPtr<Mutex<()>>);
+
+impl StaticLock {
+    pub(crate) const fn new() -> Self {
+        StaticLock(AtomicPtr::new(ptr::null_mut()))
+    }
+
+    /// Takes the lock, allocating it first if no thread has yet. A panic
+    /// while holding it poisons it, but none of the users of this lock
+    /// leave anything half-done behind, so the poison is ignored.
+    pub(crate) fn lock(&'static self) -> MutexGuard<'static, ()> {
+        let mut mutex = self.0.load(Ordering::Acquire);
+
+        if mutex.is_null() {
+            let new = Box::into_raw(Box::new(Mutex::new(())));
+            match self.0.compare_exchange(ptr::null_mut(), new, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_)      => mutex = new,
+                Err(other) => {
+                    // Another thread got there first, so use theirs.
+                    drop(unsafe { Box::from_raw(new) });
+                    mutex = other;
+                }
+            }
+        }
+
+        unsafe { &*mutex }.lock().unwrap_or_else(PoisonError::into_inner)
     }
 }
 
+/// Serialises every use of the `setpwent`/`getpwent`/`endpwent` family
+/// made through this crate, as they share one global position in the
+/// users database. The next iterator starts again from the beginning with
+/// `setpwent`, so a panic partway through doesn’t matter.
+static PWENT_LOCK: StaticLock = StaticLock::new();
+
+/// Serialises every use of the `setgrent`/`getgrent`/`endgrent` family
+/// made through this crate, for the same reason.
+static GRENT_LOCK: StaticLock = StaticLock::new();
 
 
 /// An iterator over every user present on the system.
-struct AllUsers;
+///
+/// When it’s returned from [`lock_all_users`](fn.lock_all_users.html), it
+/// holds a crate-wide lock for as long as it exists, so only one of these
+/// can be in use at a time. Iteration stops at the first error; use
+/// [`list_all_users`](fn.list_all_users.html) to find out about it.
+pub struct AllUsers {
+    _lock: Option<MutexGuard<'static, ()>>,
+
+    #[allow(dead_code)]
+    buf: Vec<c_char>,
+}
 
 /// Creates a new iterator over every user present on the system.
 ///
@@ -832,13 +1234,16 @@ struct AllUsers;
 /// These functions [modify a global
 /// state](http://man7.org/linux/man-pages/man3/getpwent.3.html#ATTRIBUTES),
 /// and if any are used at the same time, the state could be reset,
-/// resulting in a data race. We cannot even place it behind an internal
-/// `Mutex`, as there is nothing stopping another `extern` function
-/// definition from calling it!
+/// resulting in a data race. This iterator doesn’t take the lock that
+/// [`lock_all_users`](fn.lock_all_users.html) does, so it can’t deadlock,
+/// but nor is it protected from other iterators, whether they come from
+/// this crate or from another `extern` function definition.
 ///
 /// So to iterate all users, construct the iterator inside an `unsafe`
 /// block, then make sure to not make a new instance of it until
-/// iteration is over.
+/// iteration is over. Most programs should use
+/// [`list_all_users`](fn.list_all_users.html) or
+/// [`lock_all_users`](fn.lock_all_users.html) instead.
 ///
 /// # Examples
 ///
@@ -851,12 +1256,79 @@ struct AllUsers;
 /// }
 /// ```
 pub unsafe fn all_users() -> impl Iterator<Item=User> {
-    #[cfg(feature = "logging")]
-    trace!("Running setpwent");
+    AllUsers::start(None)
+}
 
-    #[cfg(not(target_os = "android"))]
-    libc::setpwent();
-    AllUsers
+/// Creates a new iterator over every user present on the system, holding
+/// this crate’s lock on the users database until it is dropped.
+///
+/// Where the C library has it, the iterator uses the reentrant
+/// `getpwent_r` function, so other threads looking up users with
+/// [`get_user_by_uid`](fn.get_user_by_uid.html) and friends are not
+/// affected.
+///
+/// # libc functions used
+///
+/// - [`getpwent_r`](https://docs.rs/libc/*/libc/fn.getpwent_r.html) or
+///   [`getpwent`](https://docs.rs/libc/*/libc/fn.getpwent.html)
+/// - [`setpwent`](https://docs.rs/libc/*/libc/fn.setpwent.html)
+/// - [`endpwent`](https://docs.rs/libc/*/libc/fn.endpwent.html)
+///
+/// # Deadlocks
+///
+/// Other threads wanting to enumerate the users wait until this iterator
+/// is dropped. Creating a second one on the *same* thread while the first
+/// is still alive will deadlock, so finish iterating first, or use
+/// [`list_all_users`](fn.list_all_users.html).
+///
+/// This lock only covers this crate. If other code in the program calls
+/// `getpwent` directly at the same time, the two can still interfere.
+///
+/// # Examples
+///
+/// ```
+/// use users::lock_all_users;
+///
+/// for user in lock_all_users() {
+///     println!("User #{} ({:?})", user.uid(), user.name());
+/// }
+/// ```
+pub fn lock_all_users() -> AllUsers {
+    AllUsers::start(Some(PWENT_LOCK.lock()))
+}
+
+/// Returns every user present on the system, read all in one go while
+/// holding this crate’s lock on the users database.
+///
+/// # libc functions used
+///
+/// The same as [`lock_all_users`](fn.lock_all_users.html).
+///
+/// # Errors
+///
+/// Where `getpwent_r` is used, an error other than `ERANGE` is returned
+/// rather than ending the list early. Plain `getpwent` can’t tell an error
+/// apart from the end of the database, so on other systems the list just
+/// ends.
+///
+/// # Examples
+///
+/// ```
+/// use users::list_all_users;
+///
+/// for user in list_all_users().unwrap() {
+///     println!("User #{} ({:?})", user.uid(), user.name());
+/// }
+/// ```
+pub fn list_all_users() -> Result<Vec<User>, LookupError> {
+    let mut iter = lock_all_users();
+    let mut users = Vec::new();
+
+    while let Some(user) = iter.next_user()? {
+        users.push(user);
+    }
+
+    Ok(users)
 }
 
 impl Drop for AllUsers {
@@ -874,31 +1346,284 @@ impl Drop for AllUsers {
     }
 }
 
-impl Iterator for AllUsers {
-    type Item = User;
+impl AllUsers {
+
+    /// Rewinds to the start of the users database, keeping hold of the
+    /// lock, if there is one, until the iterator is dropped.
+    fn start(lock: Option<MutexGuard<'static, ()>>) -> Self {
+        #[cfg(feature = "logging")]
+        trace!("Running setpwent");
+
+        #[cfg(not(target_os = "android"))]
+        unsafe { libc::setpwent() };
+
+        AllUsers { _lock: lock, buf: vec![0; 2048] }
+    }
 
     #[cfg(target_os = "android")]
-    fn next(&mut self) -> Option<User> {
-        None
+    fn next_user(&mut self) -> Result<Option<User>, LookupError> {
+        Ok(None)
     }
 
-    #[cfg(not(target_os = "android"))]
-    fn next(&mut self) -> Option<User> {
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    fn next_user(&mut self) -> Result<Option<User>, LookupError> {
+        let mut passwd = unsafe { mem::zeroed::<c_passwd>() };
+        let mut result = ptr::null_mut::<c_passwd>();
+
+        #[cfg(feature = "logging")]
+        trace!("Running getpwent_r");
+
+        loop {
+            let r = unsafe {
+                libc::getpwent_r(&mut passwd, self.buf.as_mut_ptr(), self.buf.len(), &mut result)
+            };
+
+            // On ERANGE, the entry is not consumed, so it can be read again
+            // into a bigger buffer.
+            if r != libc::ERANGE {
+                check_lookup_result("getpwent_r", r)?;
+                break;
+            }
+
+            grow_buffer("getpwent_r", &mut self.buf)?;
+        }
+
+        if result.is_null() {
+            Ok(None)
+        }
+        else {
+            let user = unsafe { passwd_to_user(passwd) };
+            Ok(Some(user))
+        }
+    }
+
+    #[cfg(not(any(target_os = "android", all(target_os = "linux", target_env = "gnu"))))]
+    fn next_user(&mut self) -> Result<Option<User>, LookupError> {
         #[cfg(feature = "logging")]
         trace!("Running getpwent");
 
         let result = unsafe { libc::getpwent() };
 
         if result.is_null() {
-            None
+            Ok(None)
         }
         else {
             let user = unsafe { passwd_to_user(result.read()) };
-            Some(user)
+            Ok(Some(user))
+        }
+    }
+}
+
+impl Iterator for AllUsers {
+    type Item = User;
+
+    fn next(&mut self) -> Option<User> {
+        self.next_user().unwrap_or(None)
+    }
+}
+
+
+/// An iterator over every group present on the system.
+///
+/// When it’s returned from [`lock_all_groups`](fn.lock_all_groups.html),
+/// it holds a crate-wide lock for as long as it exists, so only one of
+/// these can be in use at a time. Iteration stops at the first error; use
+/// [`list_all_groups`](fn.list_all_groups.html) to find out about it.
+pub struct AllGroups {
+    _lock: Option<MutexGuard<'static, ()>>,
+
+    #[allow(dead_code)]
+    buf: Vec<c_char>,
+}
+
+/// Creates a new iterator over every group present on the system.
+///
+/// # libc functions used
+///
+/// - [`getgrent`](https://docs.rs/libc/*/libc/fn.getgrent.html)
+/// - [`setgrent`](https://docs.rs/libc/*/libc/fn.setgrent.html)
+/// - [`endgrent`](https://docs.rs/libc/*/libc/fn.endgrent.html)
+///
+/// # Safety
+///
+/// This constructor is marked as `unsafe` for the same reason as
+/// [`all_users`](fn.all_users.html): the underlying C functions,
+/// `getgrent`/`setgrent`/`endgrent`, [modify a global
+/// state](http://man7.org/linux/man-pages/man3/getgrent.3.html#ATTRIBUTES),
+/// and nothing stops other code, or another one of these iterators, from
+/// using them at the same time. It doesn’t take the lock that
+/// [`lock_all_groups`](fn.lock_all_groups.html) does. Most programs should
+/// use [`list_all_groups`](fn.list_all_groups.html) or
+/// [`lock_all_groups`](fn.lock_all_groups.html) instead.
+///
+/// # Examples
+///
+/// ```
+/// use users::all_groups;
+///
+/// let iter = unsafe { all_groups() };
+/// for group in iter {
+///     println!("Group #{} ({:?})", group.gid(), group.name());
+/// }
+/// ```
+pub unsafe fn all_groups() -> impl Iterator<Item=Group> {
+    AllGroups::start(None)
+}
+
+/// Creates a new iterator over every group present on the system, holding
+/// this crate’s lock on the groups database until it is dropped.
+///
+/// Where the C library has it, the iterator uses the reentrant
+/// `getgrent_r` function.
+///
+/// # libc functions used
+///
+/// - [`getgrent_r`](https://docs.rs/libc/*/libc/fn.getgrent_r.html) or
+///   [`getgrent`](https://docs.rs/libc/*/libc/fn.getgrent.html)
+/// - [`setgrent`](https://docs.rs/libc/*/libc/fn.setgrent.html)
+/// - [`endgrent`](https://docs.rs/libc/*/libc/fn.endgrent.html)
+///
+/// # Deadlocks
+///
+/// As with [`lock_all_users`](fn.lock_all_users.html), creating a second
+/// iterator on the same thread while the first is still alive will
+/// deadlock. The users and groups locks are separate, so iterating over
+/// the users while iterating over the groups is fine.
+///
+/// # Examples
+///
+/// ```
+/// use users::lock_all_groups;
+///
+/// for group in lock_all_groups() {
+///     println!("Group #{} ({:?})", group.gid(), group.name());
+/// }
+/// ```
+pub fn lock_all_groups() -> AllGroups {
+    AllGroups::start(Some(GRENT_LOCK.lock()))
+}
+
+/// Returns every group present on the system, read all in one go while
+/// holding this crate’s lock on the groups database.
+///
+/// # libc functions used
+///
+/// The same as [`lock_all_groups`](fn.lock_all_groups.html).
+///
+/// # Errors
+///
+/// As with [`list_all_users`](fn.list_all_users.html), errors from
+/// `getgrent_r` are returned, while plain `getgrent` just ends the list.
+///
+/// # Examples
+///
+/// ```
+/// use users::list_all_groups;
+///
+/// for group in list_all_groups().unwrap() {
+///     println!("Group #{} ({:?})", group.gid(), group.name());
+/// }
+/// ```
+pub fn list_all_groups() -> Result<Vec<Group>, LookupError> {
+    let mut iter = lock_all_groups();
+    let mut groups = Vec::new();
+
+    while let Some(group) = iter.next_group()? {
+        groups.push(group);
+    }
+
+    Ok(groups)
+}
+
+impl Drop for AllGroups {
+    #[cfg(target_os = "android")]
+    fn drop(&mut self) {
+        // nothing to do here
+    }
+
+    #[cfg(not(target_os = "android"))]
+    fn drop(&mut self) {
+        #[cfg(feature = "logging")]
+        trace!("Running endgrent");
+
+        unsafe { libc::endgrent() };
+    }
+}
+
+impl AllGroups {
+
+    /// Rewinds to the start of the groups database, keeping hold of the
+    /// lock, if there is one, until the iterator is dropped.
+    fn start(lock: Option<MutexGuard<'static, ()>>) -> Self {
+        #[cfg(feature = "logging")]
+        trace!("Running setgrent");
+
+        #[cfg(not(target_os = "android"))]
+        unsafe { libc::setgrent() };
+
+        AllGroups { _lock: lock, buf: vec![0; 2048] }
+    }
+
+    #[cfg(target_os = "android")]
+    fn next_group(&mut self) -> Result<Option<Group>, LookupError> {
+        Ok(None)
+    }
+
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    fn next_group(&mut self) -> Result<Option<Group>, LookupError> {
+        let mut group = unsafe { mem::zeroed::<c_group>() };
+        let mut result = ptr::null_mut::<c_group>();
+
+        #[cfg(feature = "logging")]
+        trace!("Running getgrent_r");
+
+        loop {
+            let r = unsafe {
+                libc::getgrent_r(&mut group, self.buf.as_mut_ptr(), self.buf.len(), &mut result)
+            };
+
+            if r != libc::ERANGE {
+                check_lookup_result("getgrent_r", r)?;
+                break;
+            }
+
+            grow_buffer("getgrent_r", &mut self.buf)?;
+        }
+
+        if result.is_null() {
+            Ok(None)
+        }
+        else {
+            let group = unsafe { struct_to_group(group) };
+            Ok(Some(group))
+        }
+    }
+
+    #[cfg(not(any(target_os = "android", all(target_os = "linux", target_env = "gnu"))))]
+    fn next_group(&mut self) -> Result<Option<Group>, LookupError> {
+        #[cfg(feature = "logging")]
+        trace!("Running getgrent");
+
+        let result = unsafe { libc::getgrent() };
+
+        if result.is_null() {
+            Ok(None)
+        }
+        else {
+            let group = unsafe { struct_to_group(result.read()) };
+            Ok(Some(group))
         }
     }
 }
 
+impl Iterator for AllGroups {
+    type Item = Group;
+
+    fn next(&mut self) -> Option<Group> {
+        self.next_group().unwrap_or(None)
+    }
+}
+
 
 
 /// OS-specific extensions to users and groups.
@@ -956,6 +1681,31 @@ pub mod os {
             /// Can be used to construct tests users, which by default come with a
             /// dummy password field.
             fn with_password<S: AsRef<OsStr> + ?Sized>(self, password: &S) -> Self;
+
+            /// Returns the user’s GECOS field, which usually holds their full
+            /// name. Use [`Gecos`](../../gecos/struct.Gecos.html) to split it
+            /// into its parts.
+            ///
+            /// This method was added after the others, so it has a default
+            /// implementation for types outside this crate, which returns
+            /// an empty string.
+            fn gecos(&self) -> &OsStr {
+                OsStr::new("")
+            }
+
+            /// Sets this user’s GECOS field to the given string.
+            /// Can be used to construct test users, which by default come with
+            /// an empty GECOS field.
+            ///
+            /// Like [`gecos`](#method.gecos), this has a default implementation
+            /// for types outside this crate. **The default is a no-op:** it
+            /// ignores `gecos` and returns the user unchanged, so a type that
+            /// has a GECOS field to set must override it. `User` does.
+            fn with_gecos<S: AsRef<OsStr> + ?Sized>(self, gecos: &S) -> Self
+            where Self: Sized {
+                let _ = gecos;
+                self
+            }
         }
 
         /// Unix-specific extensions for `Group`s.
@@ -981,6 +1731,9 @@ pub mod os {
 
             /// The user’s encrypted password.
             pub password: OsString,
+
+            /// The user’s GECOS field.
+            pub gecos: OsString,
         }
 
         impl Default for UserExtras {
@@ -989,6 +1742,7 @@ pub mod os {
                     home_dir: "/var/empty".into(),
                     shell:    "/bin/false".into(),
                     password: "*".into(),
+                    gecos:    OsString::new(),
                 }
             }
         }
@@ -1006,8 +1760,9 @@ pub mod os {
                     let home_dir = from_raw_buf::<OsString>(passwd.pw_dir).into();
                     let shell    = from_raw_buf::<OsString>(passwd.pw_shell).into();
                     let password = from_raw_buf::<OsString>(passwd.pw_passwd);
+                    let gecos    = from_raw_buf::<OsString>(passwd.pw_gecos);
 
-                    Self { home_dir, shell, password }
+                    Self { home_dir, shell, password, gecos }
                 }
             }
         }
@@ -1043,6 +1798,15 @@ pub mod os {
                 self.extras.password = password.into();
                 self
             }
+
+            fn gecos(&self) -> &OsStr {
+                &self.extras.gecos
+            }
+
+            fn with_gecos<S: AsRef<OsStr> + ?Sized>(mut self, gecos: &S) -> Self {
+                self.extras.gecos = gecos.into();
+                self
+            }
         }
 
         /// Unix-specific fields for `Group`s.
@@ -1063,7 +1827,7 @@ pub mod os {
 
         impl GroupExt for Group {
             fn members(&self) -> &[OsString] {
-                &*self.extras.members
+                &self.extras.members
             }
 
             fn add_member<S: AsRef<OsStr> + ?Sized>(mut self, member: &S) -> Self {
@@ -1138,6 +1902,15 @@ pub mod os {
                 self.extras.extras.password = password.into();
                 self
             }
+
+            fn gecos(&self) -> &OsStr {
+                &self.extras.extras.gecos
+            }
+
+            fn with_gecos<S: AsRef<OsStr> + ?Sized>(mut self, gecos: &S) -> Self {
+                self.extras.extras.gecos = gecos.into();
+                self
+            }
         }
 
         /// BSD-specific accessors for `User`s.
@@ -1194,10 +1967,22 @@ mod test {
         get_current_uid();
     }
 
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd", target_os = "dragonfly", target_os = "openbsd"))]
+    #[test]
+    fn resuid() {
+        let (ruid, euid, _) = get_resuid();
+        assert_eq!(ruid, get_current_uid());
+        assert_eq!(euid, get_effective_uid());
+
+        let (rgid, egid, _) = get_resgid();
+        assert_eq!(rgid, get_current_gid());
+        assert_eq!(egid, get_effective_gid());
+    }
+
     #[test]
     fn username() {
         let uid = get_current_uid();
-        assert_eq!(&*get_current_username().unwrap(), &*get_user_by_uid(uid).unwrap().name());
+        assert_eq!(&*get_current_username().unwrap(), get_user_by_uid(uid).unwrap().name());
     }
 
     #[test]
@@ -1241,13 +2026,91 @@ mod test {
         assert!(user.is_none());
     }
 
+    #[test]
+    fn try_user_by_uid() {
+        let uid = get_current_uid();
+        let user = try_get_user_by_uid(uid).unwrap().unwrap();
+        assert_eq!(user.uid(), uid);
+    }
+
+    #[test]
+    fn try_user_by_name() {
+        let name = get_current_username().unwrap();
+        let user = try_get_user_by_name(&name).unwrap();
+        assert_eq!(user.unwrap().name(), &*name);
+
+        // A null character is not an error, it just matches nobody
+        assert!(try_get_user_by_name("user\0").unwrap().is_none());
+    }
+
+    #[test]
+    fn try_group_by_name() {
+        let cur_user = get_user_by_uid(get_current_uid()).unwrap();
+        let cur_group = try_get_group_by_gid(cur_user.primary_group).unwrap().unwrap();
+        let group_by_name = try_get_group_by_name(&cur_group.name()).unwrap();
+        assert_eq!(group_by_name.unwrap().gid(), cur_group.gid());
+
+        assert!(try_get_group_by_name("users\0").unwrap().is_none());
+    }
+
+    #[test]
+    fn lookup_error() {
+        let error = check_lookup_result("getpwuid_r", libc::EIO).unwrap_err();
+        assert_eq!(error.function(), "getpwuid_r");
+        assert_eq!(error.raw_os_error(), libc::EIO);
+        assert_eq!(io::Error::from(error).raw_os_error(), Some(libc::EIO));
+        assert!(error.to_string().starts_with("getpwuid_r failed: "));
+    }
+
+    #[test]
+    fn lookup_not_found_errnos() {
+        assert!(check_lookup_result("getpwnam_r", 0).is_ok());
+        assert!(check_lookup_result("getpwnam_r", libc::ENOENT).is_ok());
+    }
+
+    #[test]
+    fn all_groups_has_primary_group() {
+        let user = get_user_by_uid(get_current_uid()).unwrap();
+        let found = list_all_groups().unwrap().iter().any(|g| g.gid() == user.primary_group_id());
+        assert!(found);
+    }
+
+    #[test]
+    fn all_users_has_current_user() {
+        let uid = get_current_uid();
+        assert!(lock_all_users().any(|u| u.uid() == uid));
+    }
+
+    #[test]
+    fn all_users_from_many_threads() {
+        let threads: Vec<_> = (0 .. 4).map(|_| {
+            ::std::thread::spawn(|| list_all_users().unwrap().len())
+        }).collect();
+
+        let counts: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        assert!(counts.iter().all(|&c| c == counts[0]));
+    }
+
+    #[test]
+    fn nested_users_and_groups() {
+        for group in lock_all_groups().take(1) {
+            let users = list_all_users().unwrap();
+            assert!(!users.is_empty(), "no users while iterating group {:?}", group);
+        }
+    }
+
     #[test]
     fn user_get_groups() {
         let uid = get_current_uid();
         let user = get_user_by_uid(uid).unwrap();
         let groups = user.groups().unwrap();
         println!("Groups: {:?}", groups);
-        assert!(groups.len() > 0);
+        assert!(!groups.is_empty());
+    }
+
+    #[test]
+    fn duplicate_groups() {
+        assert_eq!(remove_duplicates(vec![ 100, 7, 100, 8, 7, 100 ]), vec![ 100, 7, 8 ]);
     }
 
     #[test]
@@ -1266,4 +2129,24 @@ mod test {
         let group = get_group_by_name("users\0");
         assert!(group.is_none());
     }
+
+    #[test]
+    fn user_ext_defaults() {
+        use std::path::Path;
+        use self::os::unix::UserExt;
+
+        // A type written before `gecos` was added to the trait.
+        struct OldUser;
+
+        impl UserExt for OldUser {
+            fn home_dir(&self) -> &Path { Path::new("/") }
+            fn with_home_dir<S: AsRef<OsStr> + ?Sized>(self, _: &S) -> Self { self }
+            fn shell(&self) -> &Path { Path::new("/bin/sh") }
+            fn with_shell<S: AsRef<OsStr> + ?Sized>(self, _: &S) -> Self { self }
+            fn password(&self) -> &OsStr { OsStr::new("x") }
+            fn with_password<S: AsRef<OsStr> + ?Sized>(self, _: &S) -> Self { self }
+        }
+
+        assert_eq!(OldUser.with_gecos("Fred").gecos(), "");
+    }
 }
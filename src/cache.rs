@@ -36,9 +36,9 @@
 //! and group lookups. Rust provides mutability in two ways:
 //!
 //! 1. Have its methods take `&mut self`, instead of `&self`, allowing the
-//!   internal maps to be mutated (“inherited mutability”)
+//!    internal maps to be mutated (“inherited mutability”)
 //! 2. Wrap the internal maps in a `RefCell`, allowing them to be modified
-//!   (“interior mutability”).
+//!    (“interior mutability”).
 //!
 //! Unfortunately, Rust is also very protective of references to a mutable
 //! value. In this case, switching to `&mut self` would only allow for one user
@@ -83,17 +83,81 @@
 //! the values themselves don’t count as being stored *in* the cache anymore. So
 //! it can be queried multiple times or go out of scope and the values it
 //! produces are not affected.
+//!
+//! ## Expiry and invalidation
+//!
+//! By default, a `UsersCache` holds on to every result forever, including
+//! the results saying that a user or group does not exist. Long-running
+//! programs can give the entries a time-to-live, separately for entries that
+//! were found and ones that weren’t, after which they get looked up again:
+//!
+//! ```
+//! use std::time::Duration;
+//! use users::UsersCache;
+//!
+//! let cache = UsersCache::new()
+//!     .with_positive_ttl(Duration::from_secs(600))
+//!     .with_negative_ttl(Duration::from_secs(60));
+//! ```
+//!
+//! Entries can also be thrown away by hand with `invalidate_user`,
+//! `invalidate_group`, and `clear`. These remove both the ID and the name
+//! entries together, so a user looked up by name afterwards is never
+//! matched to a stale ID. Invalidating also forgets every name that was
+//! found not to exist, so a user or group that has just been created is
+//! found by name straight away.
+//!
+//! Alternatively, calling `with_file_watching` makes the cache keep an eye
+//! on `/etc/passwd`, `/etc/group`, and `/etc/nsswitch.conf`, and drop its
+//! entries whenever one of them changes. This lets a cache live for as long
+//! as the program does without handing out renamed or deleted users.
+//!
+//! ## Sharing a cache between threads
+//!
+//! Those `RefCell`s mean that a `UsersCache` is not `Sync`, so sharing one
+//! between threads requires wrapping it in a `Mutex`, which only lets one
+//! thread query it at a time. The [`SyncUsersCache`](struct.SyncUsersCache.html)
+//! type keeps its maps behind `RwLock`s instead, so it can be shared through
+//! an `Arc` directly, and threads looking up entries that are already cached
+//! don’t block each other. Its entries never expire, so a long-running
+//! program should call `clear` to have them looked up again:
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//! use std::thread;
+//! use users::{Users, SyncUsersCache};
+//!
+//! let cache = Arc::new(SyncUsersCache::new());
+//!
+//! let handles: Vec<_> = (0 .. 4).map(|_| {
+//!     let cache = Arc::clone(&cache);
+//!     thread::spawn(move || cache.get_user_by_uid(502))
+//! }).collect();
+//!
+//! for handle in handles {
+//!     println!("{:?}", handle.join().unwrap());
+//! }
+//! ```
 
 use libc::{uid_t, gid_t};
 use std::cell::{Cell, RefCell};
-use std::collections::hash_map::Entry::{Occupied, Vacant};
 use std::collections::HashMap;
-use std::ffi::OsStr;
-use std::sync::Arc;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::hash::Hash;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
 
-use base::{User, Group, all_users};
+use base::{User, Group, LookupError, lock_all_users, lock_all_groups, user_group_ids, remove_duplicates};
 use traits::{Users, Groups};
 
+#[cfg(feature = "logging")]
+extern crate log;
+#[cfg(feature = "logging")]
+use self::log::trace;
+
 
 /// A producer of user and group instances that caches every result.
 ///
@@ -101,11 +165,15 @@ use traits::{Users, Groups};
 pub struct UsersCache {
     users:  BiMap<uid_t, User>,
     groups: BiMap<gid_t, Group>,
+    memberships: RefCell<HashMap<MembershipKey, Cached<Vec<gid_t>>>>,
 
     uid:  Cell<Option<uid_t>>,
     gid:  Cell<Option<gid_t>>,
     euid: Cell<Option<uid_t>>,
     egid: Cell<Option<gid_t>>,
+
+    ttl: Ttl,
+    watch: Option<FileWatch>,
 }
 
 /// A kinda-bi-directional `HashMap` that associates keys to values, and
@@ -115,9 +183,241 @@ pub struct UsersCache {
 /// only want to search based on usernames and group names. There wouldn’t be
 /// much point offering a “User to uid” map, as the uid is present in the
 /// `User` struct!
+///
+/// Every entry remembers when it was looked up, so it can be thrown away
+/// once it’s older than the cache’s time-to-live.
 struct BiMap<K, V> {
-    forward:  RefCell< HashMap<K, Option<Arc<V>>> >,
-    backward: RefCell< HashMap<Arc<OsStr>, Option<K>> >,
+    forward:  RefCell< HashMap<K, Cached<Arc<V>>> >,
+    backward: RefCell< HashMap<Arc<OsStr>, Cached<K>> >,
+}
+
+/// An entry in one of the maps of a `BiMap`, which is `None` when the
+/// lookup found that there was no such user or group.
+struct Cached<T> {
+    value: Option<T>,
+    fetched: Instant,
+}
+
+/// How long entries in a `UsersCache` are kept for, with `None` meaning
+/// forever. Entries for users and groups that exist have a different
+/// time-to-live from those that record that they don’t.
+#[derive(Clone, Copy, Default)]
+struct Ttl {
+    positive: Option<Duration>,
+    negative: Option<Duration>,
+}
+
+/// Group membership lists are cached by the username and primary group ID
+/// they were looked up with, and hold the IDs of the groups.
+type MembershipKey = (OsString, gid_t);
+
+/// Users and groups both have names, which are what the backward map of a
+/// `BiMap` is keyed on.
+trait Named {
+    fn name_arc(&self) -> &Arc<OsStr>;
+}
+
+impl Named for User {
+    fn name_arc(&self) -> &Arc<OsStr> {
+        &self.name_arc
+    }
+}
+
+impl Named for Group {
+    fn name_arc(&self) -> &Arc<OsStr> {
+        &self.name_arc
+    }
+}
+
+impl<T> Cached<T> {
+    fn new(value: Option<T>) -> Self {
+        Self { value, fetched: Instant::now() }
+    }
+
+    fn is_fresh(&self, ttl: Ttl) -> bool {
+        let ttl = if self.value.is_some() { ttl.positive } else { ttl.negative };
+        match ttl {
+            Some(ttl) => self.fetched.elapsed() < ttl,
+            None      => true,
+        }
+    }
+}
+
+impl<K: Copy + Eq + Hash, V: Named> BiMap<K, V> {
+    fn new() -> Self {
+        Self {
+            forward:  RefCell::new(HashMap::new()),
+            backward: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Looks up a cached entry by its ID. The outer `Option` says whether
+    /// there’s a fresh entry for the ID in the cache at all, and the inner
+    /// one whether it exists.
+    fn get_by_id(&self, id: K, ttl: Ttl) -> Option<Option<Arc<V>>> {
+        match self.forward.borrow().get(&id) {
+            Some(cached) if cached.is_fresh(ttl) => Some(cached.value.clone()),
+            _                                    => None,
+        }
+    }
+
+    /// Looks up a cached entry by its name, with the same return value as
+    /// `get_by_id`.
+    fn get_by_name(&self, name: &OsStr, ttl: Ttl) -> Option<Option<Arc<V>>> {
+        let id = match self.backward.borrow().get(name) {
+            Some(cached) if cached.is_fresh(ttl) => cached.value,
+            _                                    => return None,
+        };
+
+        match id {
+            Some(id) => self.get_by_id(id, ttl),
+            None     => Some(None),
+        }
+    }
+
+    /// Stores a value that has just been looked up, replacing whatever was
+    /// stored for its ID and name before.
+    fn insert(&self, id: K, value: V) -> Arc<V> {
+        self.remove(id);
+
+        let arc = Arc::new(value);
+        let name = Arc::clone(arc.name_arc());
+        self.forward.borrow_mut().insert(id, Cached::new(Some(Arc::clone(&arc))));
+        self.backward.borrow_mut().insert(name, Cached::new(Some(id)));
+        arc
+    }
+
+    /// Records that there is no entry with the given ID.
+    fn insert_missing_id(&self, id: K) {
+        self.remove(id);
+        self.forward.borrow_mut().insert(id, Cached::new(None));
+    }
+
+    /// Records that there is no entry with the given name.
+    fn insert_missing_name(&self, name: &OsStr) {
+        self.backward.borrow_mut().insert(Arc::from(name), Cached::new(None));
+    }
+
+    /// Removes the entry for the given ID, along with the name that pointed
+    /// to it, so the two maps never disagree.
+    fn remove(&self, id: K) {
+        let removed = self.forward.borrow_mut().remove(&id);
+
+        if let Some(value) = removed.and_then(|cached| cached.value) {
+            let mut backward = self.backward.borrow_mut();
+            let points_here = backward.get(&**value.name_arc())
+                                      .and_then(|cached| cached.value) == Some(id);
+            if points_here {
+                backward.remove(&**value.name_arc());
+            }
+        }
+    }
+
+    /// Removes every record of a name not existing. A missing name can’t
+    /// be matched to an ID, so this is the only way to be sure that a newly
+    /// created entry gets looked up again.
+    fn remove_missing_names(&self) {
+        self.backward.borrow_mut().retain(|_, cached| cached.value.is_some());
+    }
+
+    fn clear(&self) {
+        self.forward.borrow_mut().clear();
+        self.backward.borrow_mut().clear();
+    }
+}
+
+
+/// The files that, when changed, mean the entries in a `UsersCache` may no
+/// longer be accurate.
+struct FileWatch {
+    passwd:   WatchedFile,
+    group:    WatchedFile,
+    nsswitch: WatchedFile,
+
+    /// How often the files get checked. Looking at them on every single
+    /// lookup would cost more than the cache saves.
+    interval: Duration,
+    last_check: Cell<Option<Instant>>,
+}
+
+/// A file, along with what it looked like the last time we checked it.
+struct WatchedFile {
+    path: PathBuf,
+    stamp: Cell<Option<FileStamp>>,
+}
+
+/// The parts of a file’s metadata that change when it gets edited or
+/// replaced. Tools such as `useradd` write a new file and rename it over
+/// the old one, which changes the inode, even if the modification time
+/// happens to be the same.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+struct FileStamp {
+    dev:   u64,
+    ino:   u64,
+    size:  u64,
+    mtime: i64,
+    mtime_nsec: i64,
+}
+
+impl FileWatch {
+    fn new(passwd: PathBuf, group: PathBuf, nsswitch: PathBuf) -> Self {
+        Self {
+            passwd:   WatchedFile::new(passwd),
+            group:    WatchedFile::new(group),
+            nsswitch: WatchedFile::new(nsswitch),
+
+            interval: Duration::from_secs(1),
+            last_check: Cell::new(Some(Instant::now())),
+        }
+    }
+
+    /// Checks the files, if it’s been long enough since the last check,
+    /// and returns whether the users and the groups need to be dropped.
+    fn changes(&self) -> (bool, bool) {
+        if let Some(last_check) = self.last_check.get() {
+            if last_check.elapsed() < self.interval {
+                return (false, false);
+            }
+        }
+
+        self.last_check.set(Some(Instant::now()));
+
+        // Every file has to be checked so its stamp gets updated, even if
+        // an earlier one has already changed.
+        let passwd   = self.passwd.changed();
+        let group    = self.group.changed();
+        let nsswitch = self.nsswitch.changed();
+
+        (passwd || nsswitch, group || nsswitch)
+    }
+}
+
+impl WatchedFile {
+    fn new(path: PathBuf) -> Self {
+        let stamp = Cell::new(FileStamp::of(&path));
+        Self { path, stamp }
+    }
+
+    fn changed(&self) -> bool {
+        let stamp = FileStamp::of(&self.path);
+        stamp != self.stamp.replace(stamp)
+    }
+}
+
+impl FileStamp {
+    /// Reads the stamp of the file at the given path, or `None` if it can’t
+    /// be read, such as when the file doesn’t exist.
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+
+        Some(Self {
+            dev:   metadata.dev(),
+            ino:   metadata.ino(),
+            size:  metadata.size(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+        })
+    }
 }
 
 
@@ -127,20 +427,17 @@ struct BiMap<K, V> {
 impl Default for UsersCache {
     fn default() -> Self {
         Self {
-            users: BiMap {
-                forward:  RefCell::new(HashMap::new()),
-                backward: RefCell::new(HashMap::new()),
-            },
-
-            groups: BiMap {
-                forward:  RefCell::new(HashMap::new()),
-                backward: RefCell::new(HashMap::new()),
-            },
+            users:  BiMap::new(),
+            groups: BiMap::new(),
+            memberships: RefCell::new(HashMap::new()),
 
             uid:  Cell::new(None),
             gid:  Cell::new(None),
             euid: Cell::new(None),
             egid: Cell::new(None),
+
+            ttl: Ttl::default(),
+            watch: None,
         }
     }
 }
@@ -163,91 +460,246 @@ impl UsersCache {
 
     /// Creates a new cache that contains all the users present on the system.
     ///
-    /// # Safety
+    /// The users are read using [`lock_all_users`](../fn.lock_all_users.html),
+    /// which holds this crate’s lock on the users database while it runs.
+    /// If reading them fails partway through, the cache starts out with
+    /// the users that were read before the error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::cache::UsersCache;
     ///
-    /// This is `unsafe` because we cannot prevent data races if two caches
-    /// were attempted to be initialised on different threads at the same time.
-    /// For more information, see the [`all_users` documentation](../fn.all_users.html).
+    /// let cache = UsersCache::with_all_users();
+    /// ```
+    pub fn with_all_users() -> Self {
+        let cache = Self::new();
+
+        for user in lock_all_users() {
+            cache.users.insert(user.uid(), user);
+        }
+
+        cache
+    }
+
+    /// Creates a new cache that contains all the groups present on the system.
+    ///
+    /// The groups are read using [`lock_all_groups`](../fn.lock_all_groups.html),
+    /// which holds this crate’s lock on the groups database while it runs.
+    /// If reading them fails partway through, the cache starts out with
+    /// the groups that were read before the error.
     ///
     /// # Examples
     ///
     /// ```
     /// use users::cache::UsersCache;
     ///
-    /// let cache = unsafe { UsersCache::with_all_users() };
+    /// let cache = UsersCache::with_all_groups();
     /// ```
-    pub unsafe fn with_all_users() -> Self {
+    pub fn with_all_groups() -> Self {
         let cache = Self::new();
 
-        for user in all_users() {
-            let uid = user.uid();
-            let user_arc = Arc::new(user);
-            cache.users.forward.borrow_mut().insert(uid, Some(Arc::clone(&user_arc)));
-            cache.users.backward.borrow_mut().insert(Arc::clone(&user_arc.name_arc), Some(uid));
+        for group in lock_all_groups() {
+            cache.groups.insert(group.gid(), group);
         }
 
         cache
     }
-}
 
+    /// Sets how long users and groups that were found are kept in the
+    /// cache before being looked up again. By default, they are kept forever.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use users::cache::UsersCache;
+    ///
+    /// let cache = UsersCache::new().with_positive_ttl(Duration::from_secs(300));
+    /// ```
+    pub fn with_positive_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl.positive = Some(ttl);
+        self
+    }
+
+    /// Sets how long the cache remembers that a user or group does *not*
+    /// exist before looking it up again. By default, this is forever, so a
+    /// user added after the first failed lookup is never seen.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use users::cache::UsersCache;
+    ///
+    /// let cache = UsersCache::new().with_negative_ttl(Duration::from_secs(30));
+    /// ```
+    pub fn with_negative_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl.negative = Some(ttl);
+        self
+    }
+
+    /// Makes the cache drop its entries when the users or groups databases
+    /// change on disk.
+    ///
+    /// The cache records the inode, size, and modification time of
+    /// `/etc/passwd`, `/etc/group`, and `/etc/nsswitch.conf`, and checks
+    /// them again before a lookup, at most once a second. If `/etc/passwd`
+    /// has changed, every cached user is dropped; if `/etc/group` has
+    /// changed, every cached group is dropped; and if `/etc/nsswitch.conf`
+    /// has changed, both are.
+    ///
+    /// This only notices changes to the local files. Users and groups that
+    /// come from elsewhere, such as LDAP, need a time-to-live instead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::UsersCache;
+    ///
+    /// let cache = UsersCache::new().with_file_watching();
+    /// ```
+    pub fn with_file_watching(self) -> Self {
+        self.with_watched_files("/etc/passwd", "/etc/group", "/etc/nsswitch.conf")
+    }
+
+    /// Makes the cache drop its entries when any of the given files change.
+    fn with_watched_files<P: Into<PathBuf>>(mut self, passwd: P, group: P, nsswitch: P) -> Self {
+        self.watch = Some(FileWatch::new(passwd.into(), group.into(), nsswitch.into()));
+        self
+    }
+
+    /// Drops the users or groups if the files they came from have changed,
+    /// when watching them has been turned on.
+    fn check_watched_files(&self) {
+        if let Some(ref watch) = self.watch {
+            let (users_changed, groups_changed) = watch.changes();
+
+            if users_changed {
+                #[cfg(feature = "logging")]
+                trace!("Users database changed, dropping cached users");
+
+                self.users.clear();
+            }
+
+            if groups_changed {
+                #[cfg(feature = "logging")]
+                trace!("Groups database changed, dropping cached groups");
+
+                self.groups.clear();
+            }
+
+            if users_changed || groups_changed {
+                self.memberships.borrow_mut().clear();
+            }
+        }
+    }
+
+    /// Removes the user with the given ID from the cache, along with the
+    /// entry for their username, so both are looked up again next time.
+    /// Every username that was cached as missing, and every cached group
+    /// membership list, is removed too.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::{Users, UsersCache};
+    ///
+    /// let cache = UsersCache::new();
+    /// cache.get_user_by_uid(501);
+    /// cache.invalidate_user(501);
+    /// ```
+    pub fn invalidate_user(&self, uid: uid_t) {
+        self.users.remove(uid);
+        self.users.remove_missing_names();
+        self.memberships.borrow_mut().clear();
+    }
+
+    /// Removes the group with the given ID from the cache, along with the
+    /// entry for its name, so both are looked up again next time. Every
+    /// group name that was cached as missing, and every cached group
+    /// membership list, is removed too.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::{Groups, UsersCache};
+    ///
+    /// let cache = UsersCache::new();
+    /// cache.get_group_by_gid(102);
+    /// cache.invalidate_group(102);
+    /// ```
+    pub fn invalidate_group(&self, gid: gid_t) {
+        self.groups.remove(gid);
+        self.groups.remove_missing_names();
+        self.memberships.borrow_mut().clear();
+    }
+
+    /// Removes every user, group, and group membership list from the
+    /// cache, as well as the cached IDs of the current and effective user
+    /// and group.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::UsersCache;
+    ///
+    /// let cache = UsersCache::new();
+    /// cache.clear();
+    /// ```
+    pub fn clear(&self) {
+        self.users.clear();
+        self.groups.clear();
+        self.memberships.borrow_mut().clear();
 
-// TODO: stop using ‘Arc::from’ with entry API
-// The ‘get_*_by_name’ functions below create a new Arc before even testing if
-// the user exists in the cache, essentially creating an unnecessary Arc.
-// https://internals.rust-lang.org/t/pre-rfc-abandonning-morals-in-the-name-of-performance-the-raw-entry-api/7043/51
-// https://github.com/rust-lang/rfcs/pull/1769
+        self.uid.set(None);
+        self.gid.set(None);
+        self.euid.set(None);
+        self.egid.set(None);
+    }
+}
 
 
 impl Users for UsersCache {
     fn get_user_by_uid(&self, uid: uid_t) -> Option<Arc<User>> {
-        let mut users_forward = self.users.forward.borrow_mut();
+        self.try_get_user_by_uid(uid).unwrap_or(None)
+    }
 
-        let entry = match users_forward.entry(uid) {
-            Vacant(e) => e,
-            Occupied(e) => return e.get().as_ref().map(Arc::clone),
-        };
+    fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Option<Arc<User>> {
+        self.try_get_user_by_name(username).unwrap_or(None)
+    }
+
+    fn try_get_user_by_uid(&self, uid: uid_t) -> Result<Option<Arc<User>>, LookupError> {
+        self.check_watched_files();
 
-        if let Some(user) = super::get_user_by_uid(uid) {
-            let newsername = Arc::clone(&user.name_arc);
-            let mut users_backward = self.users.backward.borrow_mut();
-            users_backward.insert(newsername, Some(uid));
+        if let Some(cached) = self.users.get_by_id(uid, self.ttl) {
+            return Ok(cached);
+        }
 
-            let user_arc = Arc::new(user);
-            entry.insert(Some(Arc::clone(&user_arc)));
-            Some(user_arc)
+        // A failed lookup is returned without being cached, so the next
+        // call will try again instead of treating the user as missing.
+        if let Some(user) = super::try_get_user_by_uid(uid)? {
+            Ok(Some(self.users.insert(uid, user)))
         }
         else {
-            entry.insert(None);
-            None
+            self.users.insert_missing_id(uid);
+            Ok(None)
         }
     }
 
-    fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Option<Arc<User>> {
-        let mut users_backward = self.users.backward.borrow_mut();
-
-        let entry = match users_backward.entry(Arc::from(username.as_ref())) {
-            Vacant(e) => e,
-            Occupied(e) => {
-                return (*e.get()).and_then(|uid| {
-                    let users_forward = self.users.forward.borrow_mut();
-                    users_forward[&uid].as_ref().map(Arc::clone)
-                })
-            }
-        };
-
-        if let Some(user) = super::get_user_by_name(username) {
-            let uid = user.uid();
-            let user_arc = Arc::new(user);
+    fn try_get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Result<Option<Arc<User>>, LookupError> {
+        self.check_watched_files();
 
-            let mut users_forward = self.users.forward.borrow_mut();
-            users_forward.insert(uid, Some(Arc::clone(&user_arc)));
-            entry.insert(Some(uid));
+        if let Some(cached) = self.users.get_by_name(username.as_ref(), self.ttl) {
+            return Ok(cached);
+        }
 
-            Some(user_arc)
+        if let Some(user) = super::try_get_user_by_name(username)? {
+            Ok(Some(self.users.insert(user.uid(), user)))
         }
         else {
-            entry.insert(None);
-            None
+            self.users.insert_missing_name(username.as_ref());
+            Ok(None)
         }
     }
 
@@ -281,54 +733,42 @@ impl Users for UsersCache {
 
 impl Groups for UsersCache {
     fn get_group_by_gid(&self, gid: gid_t) -> Option<Arc<Group>> {
-        let mut groups_forward = self.groups.forward.borrow_mut();
+        self.try_get_group_by_gid(gid).unwrap_or(None)
+    }
 
-        let entry = match groups_forward.entry(gid) {
-            Vacant(e) => e,
-            Occupied(e) => return e.get().as_ref().map(Arc::clone),
-        };
+    fn get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Option<Arc<Group>> {
+        self.try_get_group_by_name(group_name).unwrap_or(None)
+    }
+
+    fn try_get_group_by_gid(&self, gid: gid_t) -> Result<Option<Arc<Group>>, LookupError> {
+        self.check_watched_files();
 
-        if let Some(group) = super::get_group_by_gid(gid) {
-            let new_group_name = Arc::clone(&group.name_arc);
-            let mut groups_backward = self.groups.backward.borrow_mut();
-            groups_backward.insert(new_group_name, Some(gid));
+        if let Some(cached) = self.groups.get_by_id(gid, self.ttl) {
+            return Ok(cached);
+        }
 
-            let group_arc = Arc::new(group);
-            entry.insert(Some(Arc::clone(&group_arc)));
-            Some(group_arc)
+        if let Some(group) = super::try_get_group_by_gid(gid)? {
+            Ok(Some(self.groups.insert(gid, group)))
         }
         else {
-            entry.insert(None);
-            None
+            self.groups.insert_missing_id(gid);
+            Ok(None)
         }
     }
 
-    fn get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Option<Arc<Group>> {
-        let mut groups_backward = self.groups.backward.borrow_mut();
-
-        let entry = match groups_backward.entry(Arc::from(group_name.as_ref())) {
-            Vacant(e) => e,
-            Occupied(e) => {
-                return (*e.get()).and_then(|gid| {
-                    let groups_forward = self.groups.forward.borrow_mut();
-                    groups_forward[&gid].as_ref().cloned()
-                });
-            }
-        };
+    fn try_get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Result<Option<Arc<Group>>, LookupError> {
+        self.check_watched_files();
 
-        if let Some(group) = super::get_group_by_name(group_name) {
-            let group_arc = Arc::new(group.clone());
-            let gid = group.gid();
-
-            let mut groups_forward = self.groups.forward.borrow_mut();
-            groups_forward.insert(gid, Some(Arc::clone(&group_arc)));
-            entry.insert(Some(gid));
+        if let Some(cached) = self.groups.get_by_name(group_name.as_ref(), self.ttl) {
+            return Ok(cached);
+        }
 
-            Some(group_arc)
+        if let Some(group) = super::try_get_group_by_name(group_name)? {
+            Ok(Some(self.groups.insert(group.gid(), group)))
         }
         else {
-            entry.insert(None);
-            None
+            self.groups.insert_missing_name(group_name.as_ref());
+            Ok(None)
         }
     }
 
@@ -357,4 +797,693 @@ impl Groups for UsersCache {
         let gid = self.get_effective_gid();
         self.get_group_by_gid(gid).map(|g| Arc::clone(&g.name_arc))
     }
+
+    fn get_user_groups<S: AsRef<OsStr> + ?Sized>(&self, username: &S, gid: gid_t) -> Option<Vec<Arc<Group>>> {
+        self.check_watched_files();
+
+        // Only the group IDs are cached, so the groups themselves expire
+        // along with the rest of the groups.
+        let key = (username.as_ref().to_os_string(), gid);
+        let cached = match self.memberships.borrow().get(&key) {
+            Some(cached) if cached.is_fresh(self.ttl) => cached.value.clone(),
+            _                                         => None,
+        };
+
+        let gids = match cached {
+            Some(gids) => gids,
+            None => {
+                let gids = remove_duplicates(user_group_ids(username.as_ref(), gid)?);
+                self.memberships.borrow_mut().insert(key, Cached::new(Some(gids.clone())));
+                gids
+            }
+        };
+
+        Some(gids.into_iter().filter_map(|gid| self.get_group_by_gid(gid)).collect())
+    }
+}
+
+
+/// A producer of user and group instances that caches every result, and
+/// that can be shared between threads.
+///
+/// It behaves the same as [`UsersCache`](struct.UsersCache.html), but is
+/// `Send` and `Sync`. For more information, see the [`users::cache` module
+/// documentation](index.html).
+pub struct SyncUsersCache {
+    users:  SyncBiMap<uid_t, User>,
+    groups: SyncBiMap<gid_t, Group>,
+    memberships: RwLock<HashMap<MembershipKey, Vec<gid_t>>>,
+
+    uid:  RwLock<Option<uid_t>>,
+    gid:  RwLock<Option<gid_t>>,
+    euid: RwLock<Option<uid_t>>,
+    egid: RwLock<Option<gid_t>>,
+}
+
+/// The thread-safe version of `BiMap`. Both directions live behind the same
+/// lock, so a reader never sees a name without the entry it points to.
+struct SyncBiMap<K, V> {
+    maps: RwLock<SyncBiMapInner<K, V>>,
+}
+
+struct SyncBiMapInner<K, V> {
+    forward:  HashMap<K, Option<Arc<V>>>,
+    backward: HashMap<Arc<OsStr>, Option<K>>,
+}
+
+impl<K: Copy + Eq + Hash, V: Named> SyncBiMap<K, V> {
+    fn new() -> Self {
+        Self {
+            maps: RwLock::new(SyncBiMapInner {
+                forward:  HashMap::new(),
+                backward: HashMap::new(),
+            }),
+        }
+    }
+
+    // Nothing panics while holding one of these locks, but if something
+    // ever does, the maps are still in a consistent state, so there’s no
+    // point in refusing to use them.
+
+    fn read(&self) -> RwLockReadGuard<'_, SyncBiMapInner<K, V>> {
+        self.maps.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, SyncBiMapInner<K, V>> {
+        self.maps.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Looks up a cached entry by its ID. The outer `Option` says whether
+    /// the ID is in the cache at all, and the inner one whether it exists.
+    fn get_by_id(&self, id: K) -> Option<Option<Arc<V>>> {
+        self.read().forward.get(&id).cloned()
+    }
+
+    /// Looks up a cached entry by its name, with the same return value as
+    /// `get_by_id`.
+    fn get_by_name(&self, name: &OsStr) -> Option<Option<Arc<V>>> {
+        let maps = self.read();
+        match maps.backward.get(name) {
+            None            => None,
+            Some(None)      => Some(None),
+            Some(Some(id))  => maps.forward.get(id).cloned(),
+        }
+    }
+
+    /// Stores an entry that has just been looked up. If another thread got
+    /// there first, its value is kept and returned instead, so the same ID
+    /// always produces the same `Arc`. If the entry has been renamed since
+    /// it was cached, the new value replaces it, and the old name no longer
+    /// points to it.
+    fn insert(&self, id: K, value: V) -> Arc<V> {
+        let mut maps = self.write();
+
+        let existing = match maps.forward.get(&id) {
+            Some(Some(existing)) => Some(Arc::clone(existing)),
+            _                    => None,
+        };
+
+        let arc = match existing {
+            Some(ref existing) if existing.name_arc() == value.name_arc() => Arc::clone(existing),
+            Some(ref existing) => {
+                let old_name = existing.name_arc();
+                if maps.backward.get(&**old_name) == Some(&Some(id)) {
+                    maps.backward.remove(&**old_name);
+                }
+                Arc::new(value)
+            }
+            None => Arc::new(value),
+        };
+
+        maps.forward.insert(id, Some(Arc::clone(&arc)));
+        maps.backward.insert(Arc::clone(arc.name_arc()), Some(id));
+        arc
+    }
+
+    /// Records that there is no entry with the given ID.
+    fn insert_missing_id(&self, id: K) {
+        self.write().forward.entry(id).or_insert(None);
+    }
+
+    /// Records that there is no entry with the given name.
+    fn insert_missing_name(&self, name: &OsStr) {
+        self.write().backward.entry(Arc::from(name)).or_insert(None);
+    }
+
+    /// Removes every entry.
+    fn clear(&self) {
+        let mut maps = self.write();
+        maps.forward.clear();
+        maps.backward.clear();
+    }
+}
+
+/// Returns the ID stored in the given lock, filling it in first if it’s
+/// empty.
+fn cached_id(lock: &RwLock<Option<u32>>, get: fn() -> u32) -> u32 {
+    if let Some(id) = *lock.read().unwrap_or_else(PoisonError::into_inner) {
+        return id;
+    }
+
+    let id = get();
+    *lock.write().unwrap_or_else(PoisonError::into_inner) = Some(id);
+    id
+}
+
+
+impl Default for SyncUsersCache {
+    fn default() -> Self {
+        Self {
+            users:  SyncBiMap::new(),
+            groups: SyncBiMap::new(),
+            memberships: RwLock::new(HashMap::new()),
+
+            uid:  RwLock::new(None),
+            gid:  RwLock::new(None),
+            euid: RwLock::new(None),
+            egid: RwLock::new(None),
+        }
+    }
+}
+
+
+impl SyncUsersCache {
+
+    /// Creates a new empty cache.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::cache::SyncUsersCache;
+    ///
+    /// let cache = SyncUsersCache::new();
+    /// ```
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new cache that contains all the users present on the system.
+    /// If reading them fails partway through, the cache starts out with the
+    /// users that were read before the error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::cache::SyncUsersCache;
+    ///
+    /// let cache = SyncUsersCache::with_all_users();
+    /// ```
+    pub fn with_all_users() -> Self {
+        let cache = Self::new();
+
+        for user in lock_all_users() {
+            cache.users.insert(user.uid(), user);
+        }
+
+        cache
+    }
+
+    /// Creates a new cache that contains all the groups present on the system.
+    /// If reading them fails partway through, the cache starts out with the
+    /// groups that were read before the error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::cache::SyncUsersCache;
+    ///
+    /// let cache = SyncUsersCache::with_all_groups();
+    /// ```
+    pub fn with_all_groups() -> Self {
+        let cache = Self::new();
+
+        for group in lock_all_groups() {
+            cache.groups.insert(group.gid(), group);
+        }
+
+        cache
+    }
+
+    /// Removes every user, group, and group membership list from the
+    /// cache, as well as the cached IDs of the current and effective user
+    /// and group.
+    ///
+    /// Nothing in a `SyncUsersCache` expires by itself, so this is how a
+    /// long-running program picks up changes to the users and groups
+    /// databases, such as a user being added to a group.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::cache::SyncUsersCache;
+    ///
+    /// let cache = SyncUsersCache::new();
+    /// cache.clear();
+    /// ```
+    pub fn clear(&self) {
+        self.users.clear();
+        self.groups.clear();
+        self.memberships.write().unwrap_or_else(PoisonError::into_inner).clear();
+
+        for id in &[&self.uid, &self.gid, &self.euid, &self.egid] {
+            *id.write().unwrap_or_else(PoisonError::into_inner) = None;
+        }
+    }
+}
+
+
+impl Users for SyncUsersCache {
+    fn get_user_by_uid(&self, uid: uid_t) -> Option<Arc<User>> {
+        self.try_get_user_by_uid(uid).unwrap_or(None)
+    }
+
+    fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Option<Arc<User>> {
+        self.try_get_user_by_name(username).unwrap_or(None)
+    }
+
+    fn try_get_user_by_uid(&self, uid: uid_t) -> Result<Option<Arc<User>>, LookupError> {
+        if let Some(cached) = self.users.get_by_id(uid) {
+            return Ok(cached);
+        }
+
+        // No lock is held while the C library is being queried, so a slow
+        // lookup doesn’t hold up threads asking for other users.
+        if let Some(user) = super::try_get_user_by_uid(uid)? {
+            Ok(Some(self.users.insert(uid, user)))
+        }
+        else {
+            self.users.insert_missing_id(uid);
+            Ok(None)
+        }
+    }
+
+    fn try_get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Result<Option<Arc<User>>, LookupError> {
+        if let Some(cached) = self.users.get_by_name(username.as_ref()) {
+            return Ok(cached);
+        }
+
+        if let Some(user) = super::try_get_user_by_name(username)? {
+            Ok(Some(self.users.insert(user.uid(), user)))
+        }
+        else {
+            self.users.insert_missing_name(username.as_ref());
+            Ok(None)
+        }
+    }
+
+    fn get_current_uid(&self) -> uid_t {
+        cached_id(&self.uid, super::get_current_uid)
+    }
+
+    fn get_current_username(&self) -> Option<Arc<OsStr>> {
+        let uid = self.get_current_uid();
+        self.get_user_by_uid(uid).map(|u| Arc::clone(&u.name_arc))
+    }
+
+    fn get_effective_uid(&self) -> uid_t {
+        cached_id(&self.euid, super::get_effective_uid)
+    }
+
+    fn get_effective_username(&self) -> Option<Arc<OsStr>> {
+        let uid = self.get_effective_uid();
+        self.get_user_by_uid(uid).map(|u| Arc::clone(&u.name_arc))
+    }
+}
+
+
+impl Groups for SyncUsersCache {
+    fn get_group_by_gid(&self, gid: gid_t) -> Option<Arc<Group>> {
+        self.try_get_group_by_gid(gid).unwrap_or(None)
+    }
+
+    fn get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Option<Arc<Group>> {
+        self.try_get_group_by_name(group_name).unwrap_or(None)
+    }
+
+    fn try_get_group_by_gid(&self, gid: gid_t) -> Result<Option<Arc<Group>>, LookupError> {
+        if let Some(cached) = self.groups.get_by_id(gid) {
+            return Ok(cached);
+        }
+
+        if let Some(group) = super::try_get_group_by_gid(gid)? {
+            Ok(Some(self.groups.insert(gid, group)))
+        }
+        else {
+            self.groups.insert_missing_id(gid);
+            Ok(None)
+        }
+    }
+
+    fn try_get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Result<Option<Arc<Group>>, LookupError> {
+        if let Some(cached) = self.groups.get_by_name(group_name.as_ref()) {
+            return Ok(cached);
+        }
+
+        if let Some(group) = super::try_get_group_by_name(group_name)? {
+            Ok(Some(self.groups.insert(group.gid(), group)))
+        }
+        else {
+            self.groups.insert_missing_name(group_name.as_ref());
+            Ok(None)
+        }
+    }
+
+    fn get_current_gid(&self) -> gid_t {
+        cached_id(&self.gid, super::get_current_gid)
+    }
+
+    fn get_current_groupname(&self) -> Option<Arc<OsStr>> {
+        let gid = self.get_current_gid();
+        self.get_group_by_gid(gid).map(|g| Arc::clone(&g.name_arc))
+    }
+
+    fn get_effective_gid(&self) -> gid_t {
+        cached_id(&self.egid, super::get_effective_gid)
+    }
+
+    fn get_effective_groupname(&self) -> Option<Arc<OsStr>> {
+        let gid = self.get_effective_gid();
+        self.get_group_by_gid(gid).map(|g| Arc::clone(&g.name_arc))
+    }
+
+    fn get_user_groups<S: AsRef<OsStr> + ?Sized>(&self, username: &S, gid: gid_t) -> Option<Vec<Arc<Group>>> {
+        let key = (username.as_ref().to_os_string(), gid);
+        let cached = self.memberships.read().unwrap_or_else(PoisonError::into_inner).get(&key).cloned();
+
+        let gids = match cached {
+            Some(gids) => gids,
+            None => {
+                let gids = remove_duplicates(user_group_ids(username.as_ref(), gid)?);
+                self.memberships.write().unwrap_or_else(PoisonError::into_inner).insert(key, gids.clone());
+                gids
+            }
+        };
+
+        Some(gids.into_iter().filter_map(|gid| self.get_group_by_gid(gid)).collect())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{SyncUsersCache, UsersCache};
+    use base::{User, list_all_groups, get_current_uid, get_current_gid, get_user_by_name};
+    use base::os::unix::GroupExt;
+    use libc::gid_t;
+    use traits::{Users, Groups};
+    use test_util::TempDir;
+
+    use std::ffi::OsStr;
+    use std::fs;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    // A uid that’s very unlikely to exist, for testing negative entries.
+    const MISSING_UID: u32 = 0xDEAD_BEEF;
+
+    #[test]
+    fn cache_same_arc() {
+        let cache = UsersCache::new();
+        let user = cache.get_user_by_uid(get_current_uid()).unwrap();
+        let same_user = cache.get_user_by_name(user.name()).unwrap();
+        assert!(Arc::ptr_eq(&user, &same_user));
+    }
+
+    #[test]
+    fn invalidate_user() {
+        let cache = UsersCache::new();
+        let user = cache.get_user_by_uid(get_current_uid()).unwrap();
+
+        cache.invalidate_user(user.uid());
+        assert!(cache.users.forward.borrow().is_empty());
+        assert!(cache.users.backward.borrow().is_empty());
+
+        let new_user = cache.get_user_by_name(user.name()).unwrap();
+        assert!(!Arc::ptr_eq(&user, &new_user));
+        assert_eq!(user.uid(), new_user.uid());
+    }
+
+    #[test]
+    fn with_all_groups() {
+        let cache = UsersCache::with_all_groups();
+        let gid = get_current_gid();
+        assert!(cache.groups.get_by_id(gid, cache.ttl).is_some());
+        assert!(cache.users.forward.borrow().is_empty());
+    }
+
+    #[test]
+    fn user_groups() {
+        let cache = UsersCache::new();
+        let user = cache.get_user_by_uid(get_current_uid()).unwrap();
+
+        let groups = cache.get_user_groups(user.name(), user.primary_group_id()).unwrap();
+        assert!(groups.iter().any(|g| g.gid() == user.primary_group_id()));
+        assert_eq!(cache.memberships.borrow().len(), 1);
+
+        let again = cache.get_user_groups(user.name(), user.primary_group_id()).unwrap();
+        assert!(Arc::ptr_eq(&groups[0], &again[0]));
+        assert!(cache.is_member(&user, user.primary_group_id()));
+
+        cache.invalidate_group(user.primary_group_id());
+        assert!(cache.memberships.borrow().is_empty());
+    }
+
+    /// Finds a user who is listed as a member of a group other than their
+    /// primary one. Not every system has one of those.
+    fn supplementary_member() -> Option<(User, gid_t)> {
+        for group in list_all_groups().unwrap() {
+            for name in group.members() {
+                if let Some(user) = get_user_by_name(name) {
+                    if user.primary_group_id() != group.gid() {
+                        return Some((user, group.gid()));
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    #[test]
+    fn user_groups_supplementary() {
+        let (user, gid) = match supplementary_member() {
+            Some(found) => found,
+            None        => return,
+        };
+
+        let cache = UsersCache::new();
+        let groups = cache.get_user_groups(user.name(), user.primary_group_id()).unwrap();
+        assert_eq!(groups[0].gid(), user.primary_group_id());
+        assert!(groups.iter().any(|g| g.gid() == gid));
+        assert!(cache.is_member(&user, gid));
+
+        let sync_cache = SyncUsersCache::new();
+        let groups = sync_cache.get_user_groups(user.name(), user.primary_group_id()).unwrap();
+        assert_eq!(groups[0].gid(), user.primary_group_id());
+        assert!(groups.iter().any(|g| g.gid() == gid));
+        assert!(sync_cache.is_member(&user, gid));
+    }
+
+    #[test]
+    fn invalidate_forgets_missing_entries() {
+        let cache = UsersCache::new();
+        assert!(cache.get_user_by_name("no-such-user\u{1}").is_none());
+        assert!(cache.get_user_by_uid(0xFFFF_FFF0).is_none());
+        assert!(cache.get_group_by_name("no-such-group\u{1}").is_none());
+
+        cache.invalidate_user(0xFFFF_FFF0);
+        assert!(cache.users.forward.borrow().is_empty());
+        assert!(cache.users.backward.borrow().is_empty());
+
+        cache.invalidate_group(0xFFFF_FFF0);
+        assert!(cache.groups.backward.borrow().is_empty());
+    }
+
+    #[test]
+    fn invalidate_group() {
+        let cache = UsersCache::new();
+        let group = cache.get_group_by_gid(get_current_gid()).unwrap();
+
+        cache.invalidate_group(group.gid());
+        assert!(cache.groups.forward.borrow().is_empty());
+        assert!(cache.groups.backward.borrow().is_empty());
+    }
+
+    #[test]
+    fn clear() {
+        let cache = UsersCache::new();
+        cache.get_user_by_uid(cache.get_current_uid());
+        cache.get_user_by_uid(MISSING_UID);
+        cache.get_group_by_name("no such group");
+
+        cache.clear();
+        assert!(cache.users.forward.borrow().is_empty());
+        assert!(cache.groups.backward.borrow().is_empty());
+        assert_eq!(cache.uid.get(), None);
+    }
+
+    #[test]
+    fn watched_passwd_change() {
+        let dir = TempDir::new("passwd_change");
+        let passwd = dir.write("passwd", "one");
+        let group = dir.write("group", "one");
+        let nsswitch = dir.write("nsswitch.conf", "one");
+
+        let mut cache = UsersCache::new().with_watched_files(&passwd, &group, &nsswitch);
+        cache.watch.as_mut().unwrap().interval = Duration::from_secs(0);
+
+        let user = cache.get_user_by_uid(get_current_uid()).unwrap();
+        let group = cache.get_group_by_gid(get_current_gid()).unwrap();
+        assert!(Arc::ptr_eq(&user, &cache.get_user_by_uid(get_current_uid()).unwrap()));
+
+        dir.write("passwd", "two lines");
+        let new_user = cache.get_user_by_uid(get_current_uid()).unwrap();
+        assert!(!Arc::ptr_eq(&user, &new_user));
+        assert!(Arc::ptr_eq(&group, &cache.get_group_by_gid(get_current_gid()).unwrap()));
+    }
+
+    #[test]
+    fn watched_nsswitch_change() {
+        let dir = TempDir::new("nsswitch_change");
+        let passwd = dir.write("passwd", "one");
+        let group = dir.write("group", "one");
+        let nsswitch = dir.write("nsswitch.conf", "one");
+
+        let mut cache = UsersCache::new().with_watched_files(&passwd, &group, &nsswitch);
+        cache.watch.as_mut().unwrap().interval = Duration::from_secs(0);
+
+        cache.get_user_by_uid(get_current_uid()).unwrap();
+        cache.get_group_by_gid(get_current_gid()).unwrap();
+
+        fs::remove_file(&nsswitch).unwrap();
+        cache.check_watched_files();
+        assert!(cache.users.forward.borrow().is_empty());
+        assert!(cache.groups.forward.borrow().is_empty());
+    }
+
+    #[test]
+    fn watched_files_within_interval() {
+        let dir = TempDir::new("within_interval");
+        let passwd = dir.write("passwd", "one");
+        let group = dir.write("group", "one");
+        let nsswitch = dir.write("nsswitch.conf", "one");
+
+        let cache = UsersCache::new().with_watched_files(&passwd, &group, &nsswitch);
+        let user = cache.get_user_by_uid(get_current_uid()).unwrap();
+
+        dir.write("passwd", "two lines");
+        assert!(Arc::ptr_eq(&user, &cache.get_user_by_uid(get_current_uid()).unwrap()));
+    }
+
+    #[test]
+    fn expired_positive_entry() {
+        let cache = UsersCache::new().with_positive_ttl(Duration::from_secs(0));
+        let user = cache.get_user_by_uid(get_current_uid()).unwrap();
+        let again = cache.get_user_by_uid(get_current_uid()).unwrap();
+        assert!(!Arc::ptr_eq(&user, &again));
+        assert_eq!(cache.users.backward.borrow().len(), 1);
+    }
+
+    #[test]
+    fn unexpired_positive_entry() {
+        let cache = UsersCache::new().with_positive_ttl(Duration::from_secs(3600));
+        let user = cache.get_user_by_uid(get_current_uid()).unwrap();
+        let again = cache.get_user_by_uid(get_current_uid()).unwrap();
+        assert!(Arc::ptr_eq(&user, &again));
+    }
+
+    #[test]
+    fn negative_ttl_leaves_positive_entries() {
+        let cache = UsersCache::new().with_negative_ttl(Duration::from_secs(0));
+        assert!(cache.get_user_by_uid(MISSING_UID).is_none());
+        assert!(cache.users.get_by_id(MISSING_UID, cache.ttl).is_none());
+
+        let user = cache.get_user_by_uid(get_current_uid()).unwrap();
+        let again = cache.get_user_by_uid(get_current_uid()).unwrap();
+        assert!(Arc::ptr_eq(&user, &again));
+    }
+
+    #[test]
+    fn sync_cache_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SyncUsersCache>();
+    }
+
+    #[test]
+    fn sync_cache_same_arc() {
+        let cache = SyncUsersCache::new();
+        let user = cache.get_user_by_uid(get_current_uid()).unwrap();
+        let same_user = cache.get_user_by_name(user.name()).unwrap();
+        assert!(Arc::ptr_eq(&user, &same_user));
+    }
+
+    #[test]
+    fn sync_cache_across_threads() {
+        let cache = Arc::new(SyncUsersCache::new());
+        let uid = get_current_uid();
+
+        let handles: Vec<_> = (0 .. 8).map(|_| {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || cache.get_user_by_uid(uid).unwrap())
+        }).collect();
+
+        let users: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        for user in &users {
+            assert!(Arc::ptr_eq(user, &users[0]));
+        }
+    }
+
+    #[test]
+    fn sync_cache_matches_cache() {
+        let sync_cache = SyncUsersCache::new();
+        let cache = UsersCache::new();
+        let gid = get_current_gid();
+
+        assert_eq!(sync_cache.get_current_uid(), cache.get_current_uid());
+        assert_eq!(sync_cache.get_group_by_gid(gid).map(|g| g.gid()),
+                   cache.get_group_by_gid(gid).map(|g| g.gid()));
+        assert_eq!(sync_cache.get_user_by_name("user\0").map(|u| u.uid()), None);
+    }
+
+    #[test]
+    fn sync_cache_rename() {
+        let cache = SyncUsersCache::new();
+        let fred = cache.users.insert(MISSING_UID, User::new(MISSING_UID, "fred", 100));
+        let again = cache.users.insert(MISSING_UID, User::new(MISSING_UID, "fred", 100));
+        assert!(Arc::ptr_eq(&fred, &again));
+
+        let renamed = cache.users.insert(MISSING_UID, User::new(MISSING_UID, "frederick", 100));
+        assert_eq!(renamed.name(), OsStr::new("frederick"));
+        assert!(cache.users.get_by_name(OsStr::new("fred")).is_none());
+
+        let by_name = cache.users.get_by_name(OsStr::new("frederick")).unwrap().unwrap();
+        assert!(Arc::ptr_eq(&by_name, &renamed));
+    }
+
+    #[test]
+    fn sync_clear() {
+        let cache = SyncUsersCache::new();
+        let user = cache.get_user_by_uid(cache.get_current_uid()).unwrap();
+        cache.get_user_groups(user.name(), user.primary_group_id());
+        cache.get_group_by_name("no such group");
+
+        cache.clear();
+        assert!(cache.users.read().forward.is_empty());
+        assert!(cache.groups.read().backward.is_empty());
+        assert!(cache.memberships.read().unwrap().is_empty());
+        assert_eq!(*cache.uid.read().unwrap(), None);
+    }
+
+    #[test]
+    fn sync_cache_user_groups() {
+        let sync_cache = SyncUsersCache::new();
+        let cache = UsersCache::new();
+        let user = cache.get_user_by_uid(get_current_uid()).unwrap();
+
+        let gids = |groups: Vec<Arc<::base::Group>>| groups.iter().map(|g| g.gid()).collect::<Vec<_>>();
+        assert_eq!(sync_cache.get_user_groups(user.name(), user.primary_group_id()).map(&gids),
+                   cache.get_user_groups(user.name(), user.primary_group_id()).map(&gids));
+        assert!(sync_cache.is_member(&user, user.primary_group_id()));
+    }
 }
@@ -0,0 +1,447 @@
+//! Linux capabilities.
+//!
+//! On Linux, root’s powers are split into separate *capabilities*, such as
+//! `CAP_NET_BIND_SERVICE` for listening on ports below 1024. Every thread
+//! has five sets of them:
+//!
+//! - the **effective** set, which the kernel actually checks;
+//! - the **permitted** set, which limits what can be made effective;
+//! - the **inheritable** set, which can be passed on through `execve`;
+//! - the **ambient** set, which is passed on through `execve` of programs
+//!   without file capabilities;
+//! - the **bounding** set, which limits what can ever be gained again.
+//!
+//! This module reads those sets, and can drop from root to another user
+//! while keeping some capabilities. Normally, changing from user 0 to any
+//! other user clears the permitted and effective sets;
+//! [`drop_privileges_keeping`](fn.drop_privileges_keeping.html) sets
+//! `PR_SET_KEEPCAPS` first so that they survive, then trims them down to the
+//! ones asked for.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use users::get_user_by_name;
+//! use users::caps::{drop_privileges_keeping, Capability, CapabilitySet};
+//!
+//! let www = get_user_by_name("www-data").unwrap();
+//! let keep = CapabilitySet::from(Capability::NET_BIND_SERVICE);
+//! drop_privileges_keeping(&www, keep).expect("Failed to drop privileges");
+//! // now running as www-data, but still able to listen on port 80
+//! ```
+
+use std::fmt;
+use std::io;
+use std::iter::FromIterator;
+use std::ops::{BitAnd, BitOr, Sub};
+
+use libc::{c_int, c_long, pid_t};
+
+use base::User;
+use switch::drop_privileges;
+
+
+/// A single capability, such as `CAP_NET_BIND_SERVICE`.
+///
+/// The associated constants cover every capability known when this was
+/// written; newer kernels may have more, which can be made with `new`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Capability(u8);
+
+#[allow(missing_docs)]
+impl Capability {
+    pub const CHOWN: Self = Capability(0);
+    pub const DAC_OVERRIDE: Self = Capability(1);
+    pub const DAC_READ_SEARCH: Self = Capability(2);
+    pub const FOWNER: Self = Capability(3);
+    pub const FSETID: Self = Capability(4);
+    pub const KILL: Self = Capability(5);
+    pub const SETGID: Self = Capability(6);
+    pub const SETUID: Self = Capability(7);
+    pub const SETPCAP: Self = Capability(8);
+    pub const LINUX_IMMUTABLE: Self = Capability(9);
+    pub const NET_BIND_SERVICE: Self = Capability(10);
+    pub const NET_BROADCAST: Self = Capability(11);
+    pub const NET_ADMIN: Self = Capability(12);
+    pub const NET_RAW: Self = Capability(13);
+    pub const IPC_LOCK: Self = Capability(14);
+    pub const IPC_OWNER: Self = Capability(15);
+    pub const SYS_MODULE: Self = Capability(16);
+    pub const SYS_RAWIO: Self = Capability(17);
+    pub const SYS_CHROOT: Self = Capability(18);
+    pub const SYS_PTRACE: Self = Capability(19);
+    pub const SYS_PACCT: Self = Capability(20);
+    pub const SYS_ADMIN: Self = Capability(21);
+    pub const SYS_BOOT: Self = Capability(22);
+    pub const SYS_NICE: Self = Capability(23);
+    pub const SYS_RESOURCE: Self = Capability(24);
+    pub const SYS_TIME: Self = Capability(25);
+    pub const SYS_TTY_CONFIG: Self = Capability(26);
+    pub const MKNOD: Self = Capability(27);
+    pub const LEASE: Self = Capability(28);
+    pub const AUDIT_WRITE: Self = Capability(29);
+    pub const AUDIT_CONTROL: Self = Capability(30);
+    pub const SETFCAP: Self = Capability(31);
+    pub const MAC_OVERRIDE: Self = Capability(32);
+    pub const MAC_ADMIN: Self = Capability(33);
+    pub const SYSLOG: Self = Capability(34);
+    pub const WAKE_ALARM: Self = Capability(35);
+    pub const BLOCK_SUSPEND: Self = Capability(36);
+    pub const AUDIT_READ: Self = Capability(37);
+    pub const PERFMON: Self = Capability(38);
+    pub const BPF: Self = Capability(39);
+    pub const CHECKPOINT_RESTORE: Self = Capability(40);
+}
+
+impl Capability {
+
+    /// The highest capability number that fits in a `CapabilitySet`.
+    pub const MAX: u8 = 63;
+
+    /// Returns the capability with the given number, or `None` if it is too
+    /// high to fit in a `CapabilitySet`.
+    pub fn new(number: u8) -> Option<Self> {
+        if number <= Self::MAX { Some(Capability(number)) }
+                           else { None }
+    }
+
+    /// Returns this capability’s number, as used by the kernel.
+    pub fn number(self) -> u8 {
+        self.0
+    }
+}
+
+impl fmt::Debug for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Capability({})", self.0)
+    }
+}
+
+
+/// A set of capabilities, such as one of a thread’s five sets.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct CapabilitySet(u64);
+
+impl CapabilitySet {
+
+    /// Returns a set with no capabilities in it.
+    pub fn empty() -> Self {
+        CapabilitySet(0)
+    }
+
+    /// Returns a set containing the capabilities whose bits are set in the
+    /// given mask, as printed in `/proc/<pid>/status`.
+    pub fn from_bits(bits: u64) -> Self {
+        CapabilitySet(bits)
+    }
+
+    /// Returns the set as a bit mask, with bit *n* set for capability *n*.
+    pub fn bits(self) -> u64 {
+        self.0
+    }
+
+    /// Returns whether the set contains the given capability.
+    pub fn contains(self, capability: Capability) -> bool {
+        self.0 & (1 << capability.0) != 0
+    }
+
+    /// Adds the given capability to the set.
+    pub fn insert(&mut self, capability: Capability) {
+        self.0 |= 1 << capability.0;
+    }
+
+    /// Removes the given capability from the set.
+    pub fn remove(&mut self, capability: Capability) {
+        self.0 &= !(1 << capability.0);
+    }
+
+    /// Returns whether the set is empty.
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns an iterator over the capabilities in the set, in order.
+    pub fn iter(self) -> impl Iterator<Item=Capability> {
+        (0 ..= Capability::MAX).map(Capability).filter(move |&c| self.contains(c))
+    }
+}
+
+impl From<Capability> for CapabilitySet {
+    fn from(capability: Capability) -> Self {
+        CapabilitySet(1 << capability.0)
+    }
+}
+
+impl FromIterator<Capability> for CapabilitySet {
+    fn from_iter<I: IntoIterator<Item=Capability>>(iter: I) -> Self {
+        let mut set = Self::empty();
+        for capability in iter {
+            set.insert(capability);
+        }
+        set
+    }
+}
+
+impl BitOr for CapabilitySet {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        CapabilitySet(self.0 | other.0)
+    }
+}
+
+impl BitAnd for CapabilitySet {
+    type Output = Self;
+
+    fn bitand(self, other: Self) -> Self {
+        CapabilitySet(self.0 & other.0)
+    }
+}
+
+impl Sub for CapabilitySet {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        CapabilitySet(self.0 & !other.0)
+    }
+}
+
+impl fmt::Debug for CapabilitySet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.iter().map(|c| c.0)).finish()
+    }
+}
+
+
+/// All five capability sets of a thread.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities {
+
+    /// The capabilities that the kernel checks.
+    pub effective: CapabilitySet,
+
+    /// The capabilities that can be made effective.
+    pub permitted: CapabilitySet,
+
+    /// The capabilities that can be passed on through `execve`.
+    pub inheritable: CapabilitySet,
+
+    /// The capabilities that are passed on through `execve` of programs
+    /// without file capabilities.
+    pub ambient: CapabilitySet,
+
+    /// The capabilities that can ever be gained.
+    pub bounding: CapabilitySet,
+}
+
+impl Capabilities {
+
+    /// Reads the capability sets of the calling thread.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` if any of the system calls fail.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::caps::{Capabilities, Capability};
+    ///
+    /// let caps = Capabilities::current().unwrap();
+    /// if caps.effective.contains(Capability::NET_BIND_SERVICE) {
+    ///     println!("This thread can listen on low ports");
+    /// }
+    /// ```
+    pub fn current() -> io::Result<Self> {
+        let (effective, permitted, inheritable) = capget()?;
+
+        let mut ambient = CapabilitySet::empty();
+        let mut bounding = CapabilitySet::empty();
+        for capability in (0 ..= Capability::MAX).map(Capability) {
+            match prctl(libc::PR_CAPBSET_READ, c_long::from(capability.0), 0) {
+                Ok(0)  => {}
+                Ok(_)  => bounding.insert(capability),
+                Err(ref e) if e.raw_os_error() == Some(libc::EINVAL) => break,  // past the last capability
+                Err(e) => return Err(e),
+            }
+
+            let is_set = c_long::from(libc::PR_CAP_AMBIENT_IS_SET);
+            match prctl(libc::PR_CAP_AMBIENT, is_set, c_long::from(capability.0)) {
+                Ok(0)  => {}
+                Ok(_)  => ambient.insert(capability),
+                Err(ref e) if e.raw_os_error() == Some(libc::EINVAL) => {}  // kernels before 4.3
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(Self { effective, permitted, inheritable, ambient, bounding })
+    }
+}
+
+
+/// Sets the effective, permitted, and inheritable capability sets of the
+/// calling thread.
+///
+/// A thread can always remove capabilities from its sets, but can only add
+/// effective ones that are in its permitted set.
+///
+/// # System calls used
+///
+/// - `capset`
+///
+/// # Errors
+///
+/// This function will return `Err` when the system call fails.
+pub fn set_capabilities(effective: CapabilitySet, permitted: CapabilitySet, inheritable: CapabilitySet) -> io::Result<()> {
+    let mut header = CapUserHeader { version: LINUX_CAPABILITY_VERSION_3, pid: 0 };
+    let data = split_sets(effective, permitted, inheritable);
+
+    match unsafe { libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()) } {
+         0 => Ok(()),
+        -1 => Err(io::Error::last_os_error()),
+         n => unreachable!("capset returned {}", n)
+    }
+}
+
+/// Permanently drops the running process’s privileges to those of the
+/// given user, like [`switch::drop_privileges`](../switch/fn.drop_privileges.html),
+/// but keeps the given capabilities in the permitted and effective sets.
+///
+/// Every other capability is removed from the permitted, effective, and
+/// inheritable sets, and `PR_SET_KEEPCAPS` is turned back off afterwards.
+/// The process has to be running as root, or have `CAP_SETUID`,
+/// `CAP_SETGID`, and the capabilities to keep.
+///
+/// # Threads
+///
+/// Call this while the process has only one thread, before spawning any
+/// others. Capabilities and `PR_SET_KEEPCAPS` belong to each thread, but
+/// glibc changes the user of every thread in the process at once. Only the
+/// calling thread keeps the capabilities. Any other thread that already
+/// exists loses all of its capabilities as its user changes, and until
+/// then it keeps running with all of root’s privileges. Threads spawned
+/// afterwards start out with the calling thread’s capabilities, as usual.
+///
+/// # Errors
+///
+/// This function will return `Err` if dropping the privileges fails, if the
+/// capabilities can’t be set, or if the sets read back afterwards aren’t
+/// the ones asked for. As with `drop_privileges`, a process that gets an
+/// error here should exit.
+pub fn drop_privileges_keeping(user: &User, keep: CapabilitySet) -> io::Result<()> {
+    prctl(libc::PR_SET_KEEPCAPS, 1, 0)?;
+
+    let result = drop_privileges(user).and_then(|()| {
+        set_capabilities(keep, keep, CapabilitySet::empty())
+    });
+
+    prctl(libc::PR_SET_KEEPCAPS, 0, 0)?;
+    result?;
+
+    let caps = Capabilities::current()?;
+    if caps.permitted != keep || caps.effective != keep || ! caps.inheritable.is_empty() {
+        let message = format!("capabilities were not kept: {:?}", caps);
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, message));
+    }
+
+    Ok(())
+}
+
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+#[repr(C)]
+struct CapUserHeader {
+    version: u32,
+    pid: pid_t,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapUserData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+fn capget() -> io::Result<(CapabilitySet, CapabilitySet, CapabilitySet)> {
+    let mut header = CapUserHeader { version: LINUX_CAPABILITY_VERSION_3, pid: 0 };
+    let mut data = [CapUserData::default(); 2];
+
+    match unsafe { libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr()) } {
+         0 => {}
+        -1 => return Err(io::Error::last_os_error()),
+         n => unreachable!("capget returned {}", n)
+    }
+
+    let join = |low: u32, high: u32| CapabilitySet(u64::from(high) << 32 | u64::from(low));
+    Ok((join(data[0].effective, data[1].effective),
+        join(data[0].permitted, data[1].permitted),
+        join(data[0].inheritable, data[1].inheritable)))
+}
+
+/// Splits the sets into the two halves that `capset` expects.
+fn split_sets(effective: CapabilitySet, permitted: CapabilitySet, inheritable: CapabilitySet) -> [CapUserData; 2] {
+    let half = |set: CapabilitySet, shift: u32| (set.0 >> shift) as u32;
+
+    [
+        CapUserData { effective: half(effective, 0),  permitted: half(permitted, 0),  inheritable: half(inheritable, 0) },
+        CapUserData { effective: half(effective, 32), permitted: half(permitted, 32), inheritable: half(inheritable, 32) },
+    ]
+}
+
+fn prctl(option: c_int, arg2: c_long, arg3: c_long) -> io::Result<c_int> {
+    let unused: c_long = 0;
+    match unsafe { libc::prctl(option, arg2, arg3, unused, unused) } {
+        -1 => Err(io::Error::last_os_error()),
+         n => Ok(n),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn set_operations() {
+        let mut set = CapabilitySet::from(Capability::NET_BIND_SERVICE);
+        set.insert(Capability::CHOWN);
+        assert!(set.contains(Capability::CHOWN));
+        assert!(!set.contains(Capability::KILL));
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![Capability::CHOWN, Capability::NET_BIND_SERVICE]);
+
+        set.remove(Capability::CHOWN);
+        assert_eq!(set, CapabilitySet::from_bits(1 << 10));
+        assert_eq!((set | Capability::KILL.into()) - set, CapabilitySet::from(Capability::KILL));
+        assert_eq!(set & CapabilitySet::from(Capability::KILL), CapabilitySet::empty());
+    }
+
+    #[test]
+    fn high_capabilities() {
+        let set = [Capability::CHECKPOINT_RESTORE, Capability::new(63).unwrap()].iter().cloned().collect::<CapabilitySet>();
+        assert_eq!(set.bits(), 1 << 40 | 1 << 63);
+        assert_eq!(Capability::new(64), None);
+
+        let halves = split_sets(set, CapabilitySet::empty(), CapabilitySet::empty());
+        assert_eq!(halves[0].effective, 0);
+        assert_eq!(halves[1].effective, 1 << 8 | 1 << 31);
+    }
+
+    #[test]
+    fn current() {
+        let caps = Capabilities::current().unwrap();
+        assert_eq!(caps.effective - caps.permitted, CapabilitySet::empty());
+        assert_eq!(caps.ambient - caps.permitted, CapabilitySet::empty());
+
+        if unsafe { libc::geteuid() } == 0 {
+            assert!(caps.effective.contains(Capability::SETUID));
+        }
+    }
+
+    #[test]
+    fn set_to_current() {
+        let caps = Capabilities::current().unwrap();
+        set_capabilities(caps.effective, caps.permitted, caps.inheritable).unwrap();
+    }
+}
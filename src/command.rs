@@ -0,0 +1,149 @@
+//! Running child processes as another user.
+//!
+//! The [`CommandExt`](trait.CommandExt.html) trait adds methods to
+//! `std::process::Command` that make the child process run as a given
+//! user, the way `runuser` or `su` do. The user’s supplementary groups are
+//! looked up before the process forks, and then the child sets its groups,
+//! group ID, and user ID, in that order, just before it executes the
+//! program.
+//!
+//! The parent process has to be running as root (or have the capabilities
+//! to change its IDs) for the child to be able to switch.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use std::process::Command;
+//! use users::get_user_by_name;
+//! use users::command::CommandExt;
+//!
+//! let fred = get_user_by_name("fred").unwrap();
+//! let status = Command::new("whoami")
+//!     .as_login_user(&fred)
+//!     .status()
+//!     .expect("Failed to run whoami");
+//! ```
+
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt as StdCommandExt;
+use std::process::Command;
+
+use libc::{gid_t, uid_t};
+
+use base::{User, user_group_ids};
+use base::os::unix::UserExt;
+use switch::{set_groups, set_all_gid, set_all_uid};
+
+
+/// Extension methods for `std::process::Command` that make the child run
+/// as another user.
+pub trait CommandExt {
+
+    /// Makes the child process run as the given user, with their primary
+    /// group and supplementary groups, and sets the `HOME`, `USER`,
+    /// `LOGNAME`, and `SHELL` environment variables from their entry.
+    ///
+    /// If the user’s groups can’t be looked up, spawning the command fails
+    /// with an error of kind `NotFound`.
+    fn as_user(&mut self, user: &User) -> &mut Self;
+
+    /// Like [`as_user`](#tymethod.as_user), but also changes into the
+    /// user’s home directory after switching, like `su -` does. The
+    /// directory is changed as the user rather than as root, so a home
+    /// directory that the user can’t enter makes spawning the command fail.
+    fn as_login_user(&mut self, user: &User) -> &mut Self;
+}
+
+impl CommandExt for Command {
+    fn as_user(&mut self, user: &User) -> &mut Self {
+        switch_in_child(self, user, false)
+    }
+
+    fn as_login_user(&mut self, user: &User) -> &mut Self {
+        switch_in_child(self, user, true)
+    }
+}
+
+/// Sets up the environment, and adds a hook that switches to the user
+/// after forking. Everything that allocates is done here, in the parent,
+/// because the hook may only make async-signal-safe calls.
+fn switch_in_child<'c>(command: &'c mut Command, user: &User, change_home: bool) -> &'c mut Command {
+    let uid: uid_t = user.uid();
+    let gid: gid_t = user.primary_group_id();
+    let groups = user_group_ids(user.name(), gid);
+    let home = if change_home { Some(CString::new(user.home_dir().as_os_str().as_bytes()).ok()) }
+                         else { None };
+
+    command.env("HOME", user.home_dir())
+           .env("USER", user.name())
+           .env("LOGNAME", user.name())
+           .env("SHELL", user.shell());
+
+    let hook = move || {
+        let groups = groups.as_ref().ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        set_groups(groups)?;
+        set_all_gid(gid)?;
+        set_all_uid(uid)?;
+
+        match home {
+            None                => Ok(()),
+            Some(Some(ref dir)) => change_dir(dir),
+            Some(None)          => Err(io::Error::from(io::ErrorKind::InvalidInput)),  // null in the path
+        }
+    };
+
+    // The hook only makes system calls, and doesn’t allocate, so it meets
+    // the requirements of `pre_exec`. That method only appeared in Rust
+    // 1.34, so the older name is used to keep building on 1.31.
+    #[allow(deprecated)]
+    command.before_exec(hook)
+}
+
+fn change_dir(dir: &CString) -> io::Result<()> {
+    match unsafe { libc::chdir(dir.as_ptr()) } {
+         0 => Ok(()),
+        -1 => Err(io::Error::last_os_error()),
+         n => unreachable!("chdir returned {}", n)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use base::{get_current_uid, get_user_by_uid};
+
+    #[test]
+    fn environment() {
+        // Setting the supplementary groups needs root, even to the same ones.
+        let _lock = ::switch::test::lock();
+        if get_current_uid() != 0 {
+            return;
+        }
+
+        let user = get_user_by_uid(get_current_uid()).unwrap().with_home_dir("/home/fred").with_shell("/bin/zsh");
+        let output = Command::new("env").as_user(&user).output().unwrap();
+        assert!(output.status.success());
+
+        let envs = String::from_utf8(output.stdout).unwrap();
+        assert!(envs.lines().any(|line| line == "HOME=/home/fred"));
+        assert!(envs.lines().any(|line| line == "SHELL=/bin/zsh"));
+        assert!(envs.lines().any(|line| line == format!("LOGNAME={}", user.name().to_str().unwrap())));
+    }
+
+    #[test]
+    fn run_as_self() {
+        // Setting the supplementary groups needs root, even to the same ones.
+        let _lock = ::switch::test::lock();
+        if get_current_uid() != 0 {
+            return;
+        }
+
+        let user = get_user_by_uid(get_current_uid()).unwrap();
+        let output = Command::new("id").arg("-u").as_user(&user).output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, format!("{}\n", user.uid()).into_bytes());
+    }
+}
@@ -0,0 +1,420 @@
+//! Working out whether an account is allowed to log in.
+//!
+//! The shadow password database and the BSD `passwd` struct both hold dates
+//! after which a password has to be changed, or an account can no longer be
+//! used. This module turns those fields into an
+//! [`AccountStatus`](enum.AccountStatus.html), following the same rules as
+//! the account management part of `pam_unix`, so that services that check
+//! accounts themselves agree with the ones that go through PAM.
+//!
+//! ## Example
+//!
+//! ```
+//! use std::time::{Duration, UNIX_EPOCH};
+//! use users::expiry::{shadow_status, AccountStatus};
+//! use users::shadow::ShadowEntry;
+//!
+//! let entry = ShadowEntry::new("fred", "$6$salt$hash")
+//!     .with_last_change(Some(19000))
+//!     .with_max_age(Some(90))
+//!     .with_warning_period(Some(7));
+//!
+//! let now = UNIX_EPOCH + Duration::from_secs(19085 * 86400);
+//! assert_eq!(shadow_status(&entry, now), AccountStatus::Warning { days_left: 5 });
+//! ```
+
+use std::os::unix::ffi::OsStrExt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "dragonfly", target_os = "openbsd", target_os = "netbsd"))]
+use libc::time_t;
+
+use base::{User, LookupError};
+use base::os::unix::UserExt;
+use shadow::{ShadowEntry, is_locked_hash};
+
+#[cfg(target_os = "linux")]
+use shadow::try_get_shadow_by_name;
+
+#[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "dragonfly", target_os = "openbsd", target_os = "netbsd"))]
+use base::os::bsd::UserExt as BsdUserExt;
+
+
+/// Whether an account can be used to log in, and whether its password has
+/// to be changed first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AccountStatus {
+
+    /// The account can be used, and its password does not need changing
+    /// any time soon.
+    Active,
+
+    /// The account can be used, but its password will expire in the given
+    /// number of days, so the user should be warned about it.
+    Warning {
+
+        /// The number of days until the password expires. This is zero on
+        /// the day that it expires.
+        days_left: i64,
+    },
+
+    /// The account can be used, but the administrator has required the
+    /// user to change their password before doing anything else.
+    PasswordMustChange,
+
+    /// The password is older than its maximum age. The user can still log
+    /// in, but only to change it.
+    PasswordExpired,
+
+    /// The password expired, and then the inactivity period after it ran
+    /// out too, so the account can no longer be used.
+    Inactive,
+
+    /// The account has passed its expiry date, and can no longer be used.
+    AccountExpired,
+
+    /// The password has been locked (it starts with `!` or `*`), so the
+    /// user can’t log in with a password at all.
+    Locked,
+}
+
+impl AccountStatus {
+
+    /// Whether the user can log in with this status, even if they then have
+    /// to change their password.
+    pub fn can_log_in(self) -> bool {
+        match self {
+            AccountStatus::Active              |
+            AccountStatus::Warning { .. }      |
+            AccountStatus::PasswordMustChange  |
+            AccountStatus::PasswordExpired     => true,
+
+            AccountStatus::Inactive            |
+            AccountStatus::AccountExpired      |
+            AccountStatus::Locked              => false,
+        }
+    }
+
+    /// Whether the user has to change their password after logging in.
+    #[allow(clippy::match_like_matches_macro)]  // `matches!` needs Rust 1.42
+    pub fn must_change_password(self) -> bool {
+        match self {
+            AccountStatus::PasswordMustChange  |
+            AccountStatus::PasswordExpired     => true,
+            _                                  => false,
+        }
+    }
+}
+
+
+/// Returns the status of the account with the given shadow entry at the
+/// given time.
+///
+/// The checks are the ones `pam_unix` makes, in the same order, with a
+/// check for a locked password added before the password aging ones:
+///
+/// 1. The account is expired if today is on or after its expiry date.
+/// 2. The account is locked if its password starts with `!` or `*`. A
+///    locked password can’t be used however old it is, so changing it
+///    wouldn’t help.
+/// 3. The password must be changed if it was last changed on day zero.
+/// 4. A last change date in the future is treated as active.
+/// 5. The account is inactive if more days than the maximum age *and*
+///    the inactivity period have passed since the last change.
+/// 6. The password is expired if more days than the maximum age have
+///    passed since the last change.
+/// 7. The user is warned if they are within the warning period of the
+///    password expiring.
+///
+/// Some tools write huge numbers into these fields to mean “never”. A
+/// limit that is too far away to add up without overflowing is treated as
+/// never being reached, the way `pam_unix` does.
+pub fn shadow_status(entry: &ShadowEntry, now: SystemTime) -> AccountStatus {
+    let today = days_since_epoch(now);
+
+    if let Some(expire) = entry.expire_date() {
+        if today >= expire {
+            return AccountStatus::AccountExpired;
+        }
+    }
+
+    if is_locked_hash(entry.password().as_bytes()) {
+        return AccountStatus::Locked;
+    }
+
+    let last_change = match entry.last_change() {
+        Some(0)    => return AccountStatus::PasswordMustChange,
+        Some(days) => days,
+        None       => return AccountStatus::Active,
+    };
+
+    if today < last_change {
+        // pam_unix logs a warning about this, but lets the user in.
+        return AccountStatus::Active;
+    }
+
+    let age = today - last_change;
+
+    if let Some(max) = entry.max_age() {
+        if let Some(inactive) = entry.inactive_period() {
+            match max.checked_add(inactive) {
+                Some(limit) if age > max && age > inactive && age > limit => {
+                    return AccountStatus::Inactive;
+                }
+                _ => {}
+            }
+        }
+
+        if age > max {
+            return AccountStatus::PasswordExpired;
+        }
+    }
+
+    if let (Some(max), Some(warn)) = (entry.max_age(), entry.warning_period()) {
+        match max.checked_sub(warn) {
+            Some(start) if age > start => {
+                // The password hasn’t expired yet, so this can’t overflow.
+                return AccountStatus::Warning { days_left: max - age };
+            }
+            _ => {}
+        }
+    }
+
+    AccountStatus::Active
+}
+
+/// Returns the status of the account with the given BSD password change
+/// and account expiry times at the given time. A time of zero means that
+/// there is no such limit.
+///
+/// This follows BSD `login`: the account is expired once its expiry time
+/// has passed, the password is expired once its change time has passed,
+/// and the user is warned when the change time is less than two weeks
+/// away. As with `shadow_status`, an account with a locked password is
+/// reported as locked rather than as having an expired password.
+#[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "dragonfly", target_os = "openbsd", target_os = "netbsd"))]
+pub fn bsd_status(user: &User, now: SystemTime) -> AccountStatus {
+    let now = secs_since_epoch(now);
+    let expire = time_to_secs(user.password_expire_time());
+    let change = time_to_secs(user.password_change_time());
+    bsd_times_status(user.password().as_bytes(), change, expire, now)
+}
+
+/// The checks made by `bsd_status`, on times in seconds since the epoch.
+/// A locked password is reported before an expired one, the same as in
+/// `shadow_status`.
+#[cfg(any(test, target_os = "macos", target_os = "freebsd", target_os = "dragonfly", target_os = "openbsd", target_os = "netbsd"))]
+fn bsd_times_status(password: &[u8], change: i64, expire: i64, now: i64) -> AccountStatus {
+    const WARNING_SECS: i64 = 14 * SECS_PER_DAY;
+
+    if expire != 0 && now >= expire {
+        return AccountStatus::AccountExpired;
+    }
+
+    if is_locked_hash(password) {
+        return AccountStatus::Locked;
+    }
+
+    if change != 0 && now >= change {
+        return AccountStatus::PasswordExpired;
+    }
+
+    if change != 0 && change - now < WARNING_SECS {
+        return AccountStatus::Warning { days_left: (change - now) / SECS_PER_DAY };
+    }
+
+    AccountStatus::Active
+}
+
+/// Returns the status of the given user’s account at the given time.
+///
+/// On Linux, this looks the user up in the shadow database and passes
+/// their entry to [`shadow_status`](fn.shadow_status.html). Users without a
+/// shadow entry have no aging information, so they are only checked for a
+/// locked password. On the BSDs, this uses
+/// [`bsd_status`](fn.bsd_status.html) instead.
+///
+/// # Errors
+///
+/// This function will return `Err` if the shadow database can’t be read,
+/// which is usually because the process is not running as root. On other
+/// systems, where this crate doesn’t know where to find the aging
+/// information, it returns an error with `ENOSYS`.
+pub fn account_status(user: &User, now: SystemTime) -> Result<AccountStatus, LookupError> {
+    #[cfg(target_os = "linux")]
+    {
+        match try_get_shadow_by_name(user.name())? {
+            Some(entry) => Ok(shadow_status(&entry, now)),
+            None        => Ok(unaged_status(user.password().as_bytes())),
+        }
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "dragonfly", target_os = "openbsd", target_os = "netbsd"))]
+    {
+        Ok(bsd_status(user, now))
+    }
+
+    #[cfg(any(target_os = "android", target_os = "solaris"))]
+    {
+        let _ = now;
+        Ok(unaged_status(user.password().as_bytes()))
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "solaris",
+                  target_os = "macos", target_os = "freebsd", target_os = "dragonfly", target_os = "openbsd", target_os = "netbsd")))]
+    {
+        let _ = (user, now);
+        Err(LookupError::new("account_status", libc::ENOSYS))
+    }
+}
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// The status of an account with no password aging information.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "solaris"))]
+fn unaged_status(password: &[u8]) -> AccountStatus {
+    if is_locked_hash(password) { AccountStatus::Locked }
+                           else { AccountStatus::Active }
+}
+
+fn secs_since_epoch(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since)   => since.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64),
+    }
+}
+
+/// Returns the day number of the given time, the way `pam_unix` counts
+/// them, rounding down.
+fn days_since_epoch(time: SystemTime) -> i64 {
+    let secs = secs_since_epoch(time);
+    if secs >= 0 { secs / SECS_PER_DAY }
+            else { (secs + 1) / SECS_PER_DAY - 1 }
+}
+
+#[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "dragonfly", target_os = "openbsd", target_os = "netbsd"))]
+#[allow(clippy::useless_conversion)]
+fn time_to_secs(time: time_t) -> i64 {
+    i64::from(time)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn day(days: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(days * 86400 + 3600)
+    }
+
+    fn aged() -> ShadowEntry {
+        ShadowEntry::new("fred", "$6$salt$hash")
+            .with_last_change(Some(1000))
+            .with_max_age(Some(90))
+            .with_warning_period(Some(7))
+            .with_inactive_period(Some(30))
+    }
+
+    #[test]
+    fn active() {
+        assert_eq!(shadow_status(&aged(), day(1010)), AccountStatus::Active);
+        assert_eq!(shadow_status(&ShadowEntry::new("fred", "$6$salt$hash"), day(1010)), AccountStatus::Active);
+    }
+
+    #[test]
+    fn warning() {
+        assert_eq!(shadow_status(&aged(), day(1083)), AccountStatus::Active);
+        assert_eq!(shadow_status(&aged(), day(1084)), AccountStatus::Warning { days_left: 6 });
+        assert_eq!(shadow_status(&aged(), day(1090)), AccountStatus::Warning { days_left: 0 });
+    }
+
+    #[test]
+    fn password_expired() {
+        assert_eq!(shadow_status(&aged(), day(1091)), AccountStatus::PasswordExpired);
+        assert_eq!(shadow_status(&aged(), day(1120)), AccountStatus::PasswordExpired);
+    }
+
+    #[test]
+    fn inactive() {
+        assert_eq!(shadow_status(&aged(), day(1121)), AccountStatus::Inactive);
+        assert!(!AccountStatus::Inactive.can_log_in());
+    }
+
+    #[test]
+    fn must_change() {
+        let entry = aged().with_last_change(Some(0));
+        assert_eq!(shadow_status(&entry, day(1010)), AccountStatus::PasswordMustChange);
+        assert!(AccountStatus::PasswordMustChange.must_change_password());
+    }
+
+    #[test]
+    fn account_expired() {
+        let entry = aged().with_expire_date(Some(1050));
+        assert_eq!(shadow_status(&entry, day(1049)), AccountStatus::Active);
+        assert_eq!(shadow_status(&entry, day(1050)), AccountStatus::AccountExpired);
+
+        let entry = entry.with_last_change(Some(0));
+        assert_eq!(shadow_status(&entry, day(1050)), AccountStatus::AccountExpired);
+    }
+
+    #[test]
+    fn future_change_date() {
+        assert_eq!(shadow_status(&aged(), day(900)), AccountStatus::Active);
+    }
+
+    #[test]
+    fn locked() {
+        let entry = ShadowEntry::new("fred", "!$6$salt$hash").with_last_change(Some(1000));
+        assert_eq!(shadow_status(&entry, day(1010)), AccountStatus::Locked);
+        assert_eq!(shadow_status(&ShadowEntry::new("daemon", "*"), day(1010)), AccountStatus::Locked);
+    }
+
+    #[test]
+    fn locked_with_expired_password() {
+        let entry = ShadowEntry::new("fred", "!$6$salt$hash")
+            .with_last_change(Some(1000))
+            .with_max_age(Some(90))
+            .with_inactive_period(Some(30));
+        assert_eq!(shadow_status(&entry, day(1091)), AccountStatus::Locked);
+        assert_eq!(shadow_status(&entry, day(1121)), AccountStatus::Locked);
+        assert_eq!(shadow_status(&entry.with_last_change(Some(0)), day(1010)), AccountStatus::Locked);
+    }
+
+    #[test]
+    fn locked_but_expired() {
+        let entry = ShadowEntry::new("fred", "!$6$salt$hash").with_expire_date(Some(10));
+        assert_eq!(shadow_status(&entry, day(1010)), AccountStatus::AccountExpired);
+    }
+
+    #[test]
+    #[allow(clippy::legacy_numeric_constants)]  // `i64::MAX` needs Rust 1.43
+    fn huge_limits() {
+        let entry = aged()
+            .with_max_age(Some(i64::max_value()))
+            .with_inactive_period(Some(i64::max_value()));
+        assert_eq!(shadow_status(&entry, day(1010)), AccountStatus::Active);
+        assert_eq!(shadow_status(&entry.with_warning_period(Some(i64::max_value())), day(1010)),
+                   AccountStatus::Warning { days_left: i64::max_value() - 10 });
+
+        let entry = aged().with_inactive_period(Some(i64::max_value()));
+        assert_eq!(shadow_status(&entry, day(5000)), AccountStatus::PasswordExpired);
+
+        let entry = aged().with_warning_period(Some(i64::min_value()));
+        assert_eq!(shadow_status(&entry, day(1085)), AccountStatus::Active);
+    }
+
+    #[test]
+    fn bsd_locked_with_expired_password() {
+        let secs = |days: i64| days * SECS_PER_DAY;
+        assert_eq!(bsd_times_status(b"$2b$hash", secs(100), 0, secs(101)), AccountStatus::PasswordExpired);
+        assert_eq!(bsd_times_status(b"*LOCKED*$2b$hash", secs(100), 0, secs(101)), AccountStatus::Locked);
+        assert_eq!(bsd_times_status(b"*LOCKED*$2b$hash", secs(100), secs(50), secs(101)), AccountStatus::AccountExpired);
+        assert_eq!(bsd_times_status(b"$2b$hash", secs(100), 0, secs(90)), AccountStatus::Warning { days_left: 10 });
+    }
+
+    #[test]
+    fn before_epoch() {
+        assert_eq!(days_since_epoch(UNIX_EPOCH - Duration::from_secs(1)), -1);
+    }
+}
@@ -0,0 +1,1054 @@
+//! Reading and writing `passwd(5)` and `group(5)` files directly.
+//!
+//! The rest of this crate asks the C library for users and groups, which
+//! goes through NSS and always reads the databases of the system the program
+//! is running on. Sometimes that’s not what you want: you might be looking
+//! inside a container image or a chroot, or generating test fixtures. This
+//! module parses files in the `/etc/passwd` and `/etc/group` formats into
+//! [`User`](../struct.User.html) and [`Group`](../struct.Group.html) values,
+//! and writes them back out again.
+//!
+//! Comments, blank lines, NIS compatibility lines (those starting with `+`
+//! or `-`), and lines that can’t be read are kept as they are, as is the way
+//! each line ends, so a file that gets read, changed, and written back only
+//! differs in the entries that were changed.
+//!
+//! ## Example
+//!
+//! ```
+//! use users::files::PasswdFile;
+//! use users::os::unix::UserExt;
+//!
+//! let passwd = PasswdFile::parse("# The admin\nroot:x:0:0:root:/root:/bin/sh\n");
+//! let root = passwd.users().next().unwrap();
+//! assert_eq!(root.uid(), 0);
+//! assert_eq!(root.shell().to_str(), Some("/bin/sh"));
+//! assert_eq!(passwd.to_bytes(), b"# The admin\nroot:x:0:0:root:/root:/bin/sh\n");
+//! ```
+//!
+//! ## Looking inside another root directory
+//!
+//! A [`RootUsers`](struct.RootUsers.html) reads `etc/passwd` and `etc/group`
+//! from under a directory, such as an unpacked container image, and answers
+//! the `Users` and `Groups` traits from them, so code written against those
+//! traits can look up IDs as that filesystem would see them:
+//!
+//! ```no_run
+//! use users::Users;
+//! use users::files::RootUsers;
+//!
+//! let image = RootUsers::open("/var/lib/images/debian").unwrap();
+//! if let Some(user) = image.get_user_by_uid(1000) {
+//!     println!("1000 is {:?} in the image", user.name());
+//! }
+//! ```
+//!
+//! ## Errors
+//!
+//! Lines that look like entries but can’t be read, such as ones with the
+//! wrong number of fields, a user ID that isn’t a number, or whitespace
+//! before the name, don’t stop the rest of the file from being read.
+//! They’re kept as `Invalid` lines, along with a [`ParseError`](struct.ParseError.html)
+//! that says what was wrong with them, which `errors` returns:
+//!
+//! ```
+//! use users::files::PasswdFile;
+//!
+//! let passwd = PasswdFile::parse("root:x:0:0:root:/root:/bin/sh\nbroken\n");
+//! assert_eq!(passwd.users().count(), 1);
+//! assert_eq!(passwd.errors().next().unwrap().line(), 2);
+//! assert_eq!(passwd.to_bytes(), b"root:x:0:0:root:/root:/bin/sh\nbroken\n");
+//! ```
+
+use std::error::Error;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use libc::{uid_t, gid_t};
+
+use base::{User, Group, get_current_uid, get_current_gid, get_effective_uid, get_effective_gid};
+use base::os::unix::{UserExt, GroupExt};
+use traits::{Users, Groups};
+
+
+/// The contents of a file in the `passwd(5)` format.
+#[derive(Clone, Debug, Default)]
+pub struct PasswdFile {
+    lines: Vec<PasswdLine>,
+}
+
+/// One line of a `passwd(5)` file.
+#[derive(Clone, Debug)]
+pub enum PasswdLine {
+
+    /// A user entry.
+    User(User, LineEnding),
+
+    /// A comment, a blank line, or a NIS compatibility line, kept exactly
+    /// as it was read, without its line ending.
+    Other(OsString, LineEnding),
+
+    /// A line that looks like an entry but can’t be read as a user, kept
+    /// exactly as it was read, along with the reason it couldn’t be read.
+    Invalid(OsString, ParseError, LineEnding),
+}
+
+/// The contents of a file in the `group(5)` format.
+#[derive(Clone, Debug, Default)]
+pub struct GroupFile {
+    lines: Vec<GroupLine>,
+}
+
+/// One line of a `group(5)` file.
+#[derive(Clone, Debug)]
+pub enum GroupLine {
+
+    /// A group entry, along with its password field, which has nowhere to
+    /// go in a `Group`.
+    Group(Group, OsString, LineEnding),
+
+    /// A comment, a blank line, or a NIS compatibility line, kept exactly
+    /// as it was read, without its line ending.
+    Other(OsString, LineEnding),
+
+    /// A line that looks like an entry but can’t be read as a group, kept
+    /// exactly as it was read, along with the reason it couldn’t be read.
+    Invalid(OsString, ParseError, LineEnding),
+}
+
+/// How a line ended in the file it was read from, so it can be written
+/// back the same way.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LineEnding {
+
+    /// A newline, `\n`. Lines added to a file end like this.
+    Lf,
+
+    /// A carriage return and a newline, `\r\n`.
+    CrLf,
+
+    /// Nothing, because this was the last line of a file that didn’t end
+    /// with a newline. If more lines get added after it, it’s written with
+    /// a newline after all, so the lines stay apart.
+    None,
+}
+
+impl LineEnding {
+    fn as_bytes(self, last: bool) -> &'static [u8] {
+        match self {
+            LineEnding::Lf            => b"\n",
+            LineEnding::CrLf          => b"\r\n",
+            LineEnding::None if last  => b"",
+            LineEnding::None          => b"\n",
+        }
+    }
+}
+
+
+impl PasswdFile {
+
+    /// Creates a new file with no lines in it.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses the contents of a `passwd(5)` file. Lines that can’t be read
+    /// as users are kept as `Invalid` lines, and reported by
+    /// [`errors`](#method.errors).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::files::PasswdFile;
+    ///
+    /// let passwd = PasswdFile::parse("fred:x:1000:1000::/home/fred:/bin/bash\n");
+    /// assert_eq!(passwd.users().count(), 1);
+    /// assert!(passwd.errors().next().is_none());
+    /// ```
+    pub fn parse<S: AsRef<[u8]> + ?Sized>(input: &S) -> Self {
+        let mut lines = Vec::new();
+
+        for (number, line, ending) in split_lines(input.as_ref()) {
+            if is_other_line(line) {
+                lines.push(PasswdLine::Other(bytes_to_os(line), ending));
+                continue;
+            }
+
+            match parse_user(line, number) {
+                Ok(user)   => lines.push(PasswdLine::User(user, ending)),
+                Err(error) => lines.push(PasswdLine::Invalid(bytes_to_os(line), error, ending)),
+            }
+        }
+
+        Self { lines }
+    }
+
+    /// Reads and parses the `passwd(5)` file at the given path.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` when the file can’t be read. Lines
+    /// that can’t be parsed are not an error; see [`errors`](#method.errors).
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let contents = fs::read(path)?;
+        Ok(Self::parse(&contents))
+    }
+
+    /// Returns every line of the file, including comments.
+    pub fn lines(&self) -> &[PasswdLine] {
+        &self.lines
+    }
+
+    /// Returns every line of the file mutably, so entries can be changed,
+    /// added, or removed.
+    pub fn lines_mut(&mut self) -> &mut Vec<PasswdLine> {
+        &mut self.lines
+    }
+
+    /// Returns an iterator over the users in the file, in order.
+    pub fn users(&self) -> impl Iterator<Item=&User> {
+        self.lines.iter().filter_map(|line| match *line {
+            PasswdLine::User(ref user, _)  => Some(user),
+            PasswdLine::Other(..)          |
+            PasswdLine::Invalid(..)        => None,
+        })
+    }
+
+    /// Returns an iterator over the reasons that the lines which couldn’t
+    /// be read as users were rejected, in order.
+    pub fn errors(&self) -> impl Iterator<Item=&ParseError> {
+        self.lines.iter().filter_map(|line| match *line {
+            PasswdLine::Invalid(_, ref error, _)  => Some(error),
+            PasswdLine::User(..)                  |
+            PasswdLine::Other(..)                 => None,
+        })
+    }
+
+    /// Adds a user to the end of the file.
+    pub fn add_user(&mut self, user: User) {
+        self.lines.push(PasswdLine::User(user, LineEnding::Lf));
+    }
+
+    /// Writes the file out in the `passwd(5)` format.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` if writing fails, or with an error of
+    /// kind `InvalidData` if a field contains a colon, a newline, or a
+    /// carriage return, which would make the file unreadable.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for (i, line) in self.lines.iter().enumerate() {
+            let ending = match *line {
+                PasswdLine::User(ref user, ending) => {
+                    write_fields(writer, &[
+                        user.name().as_bytes(),
+                        user.password().as_bytes(),
+                        user.uid().to_string().as_bytes(),
+                        user.primary_group_id().to_string().as_bytes(),
+                        user.gecos().as_bytes(),
+                        user.home_dir().as_os_str().as_bytes(),
+                        user.shell().as_os_str().as_bytes(),
+                    ])?;
+                    ending
+                }
+                PasswdLine::Other(ref text, ending)       |
+                PasswdLine::Invalid(ref text, _, ending)  => {
+                    writer.write_all(text.as_bytes())?;
+                    ending
+                }
+            };
+
+            writer.write_all(ending.as_bytes(i + 1 == self.lines.len()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the file in the `passwd(5)` format.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if a field contains a colon, a newline, or a
+    /// carriage return. Use `write_to` to handle this as an error instead.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.write_to(&mut bytes).expect("Invalid passwd field");
+        bytes
+    }
+}
+
+
+impl GroupFile {
+
+    /// Creates a new file with no lines in it.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses the contents of a `group(5)` file. The comma-separated
+    /// members list ends up in the groups’
+    /// [`members`](../os/unix/trait.GroupExt.html#tymethod.members).
+    ///
+    /// Lines that can’t be read as groups are kept as `Invalid` lines, and
+    /// reported by [`errors`](#method.errors).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::files::GroupFile;
+    /// use users::os::unix::GroupExt;
+    ///
+    /// let group = GroupFile::parse("wheel:x:10:alice,bob\n");
+    /// assert_eq!(group.groups().next().unwrap().members().len(), 2);
+    /// ```
+    pub fn parse<S: AsRef<[u8]> + ?Sized>(input: &S) -> Self {
+        let mut lines = Vec::new();
+
+        for (number, line, ending) in split_lines(input.as_ref()) {
+            if is_other_line(line) {
+                lines.push(GroupLine::Other(bytes_to_os(line), ending));
+                continue;
+            }
+
+            match parse_group(line, number) {
+                Ok((group, password))  => lines.push(GroupLine::Group(group, password, ending)),
+                Err(error)             => lines.push(GroupLine::Invalid(bytes_to_os(line), error, ending)),
+            }
+        }
+
+        Self { lines }
+    }
+
+    /// Reads and parses the `group(5)` file at the given path.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` when the file can’t be read. Lines
+    /// that can’t be parsed are not an error; see [`errors`](#method.errors).
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let contents = fs::read(path)?;
+        Ok(Self::parse(&contents))
+    }
+
+    /// Returns every line of the file, including comments.
+    pub fn lines(&self) -> &[GroupLine] {
+        &self.lines
+    }
+
+    /// Returns every line of the file mutably, so entries can be changed,
+    /// added, or removed.
+    pub fn lines_mut(&mut self) -> &mut Vec<GroupLine> {
+        &mut self.lines
+    }
+
+    /// Returns an iterator over the groups in the file, in order.
+    pub fn groups(&self) -> impl Iterator<Item=&Group> {
+        self.lines.iter().filter_map(|line| match *line {
+            GroupLine::Group(ref group, ..) => Some(group),
+            GroupLine::Other(..)            |
+            GroupLine::Invalid(..)          => None,
+        })
+    }
+
+    /// Returns an iterator over the reasons that the lines which couldn’t
+    /// be read as groups were rejected, in order.
+    pub fn errors(&self) -> impl Iterator<Item=&ParseError> {
+        self.lines.iter().filter_map(|line| match *line {
+            GroupLine::Invalid(_, ref error, _)  => Some(error),
+            GroupLine::Group(..)                 |
+            GroupLine::Other(..)                 => None,
+        })
+    }
+
+    /// Adds a group to the end of the file, with its password field set to
+    /// `x`.
+    pub fn add_group(&mut self, group: Group) {
+        self.lines.push(GroupLine::Group(group, OsString::from("x"), LineEnding::Lf));
+    }
+
+    /// Writes the file out in the `group(5)` format.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` if writing fails, or with an error of
+    /// kind `InvalidData` if a field contains a colon, a newline, or a
+    /// carriage return, or a member’s name contains a comma, which would
+    /// make the file unreadable.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for (i, line) in self.lines.iter().enumerate() {
+            let ending = match *line {
+                GroupLine::Group(ref group, ref password, ending) => {
+                    if group.members().iter().any(|m| m.as_bytes().contains(&b',')) {
+                        return Err(invalid_field());
+                    }
+
+                    let members = group.members().iter()
+                                       .map(|m| m.as_bytes())
+                                       .collect::<Vec<_>>()
+                                       .join(&b',');
+
+                    write_fields(writer, &[
+                        group.name().as_bytes(),
+                        password.as_bytes(),
+                        group.gid().to_string().as_bytes(),
+                        &members,
+                    ])?;
+                    ending
+                }
+                GroupLine::Other(ref text, ending)       |
+                GroupLine::Invalid(ref text, _, ending)  => {
+                    writer.write_all(text.as_bytes())?;
+                    ending
+                }
+            };
+
+            writer.write_all(ending.as_bytes(i + 1 == self.lines.len()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the file in the `group(5)` format.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if a field contains a colon, a newline, or a
+    /// carriage return, or if a member’s name contains a comma. Use
+    /// `write_to` to handle this as an error instead.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.write_to(&mut bytes).expect("Invalid group field");
+        bytes
+    }
+}
+
+
+/// A users and groups table read from the `etc/passwd` and `etc/group`
+/// files under a root directory, rather than from the running system.
+///
+/// The files are read once, when the table is opened. When a file lists the
+/// same ID or name more than once, the first entry wins, as it does with
+/// the C library’s own files backend.
+///
+/// The current and effective user and group IDs are still those of the
+/// running process, as there is no other process to ask, but their names
+/// are looked up in the root’s files.
+///
+/// The files are opened by joining their paths onto the root, so symbolic
+/// links inside the root that point to absolute paths are followed on the
+/// host filesystem.
+#[derive(Clone, Debug)]
+pub struct RootUsers {
+    root: PathBuf,
+    users: HashMap<uid_t, Arc<User>>,
+    users_by_name: HashMap<Arc<OsStr>, Arc<User>>,
+    groups: HashMap<gid_t, Arc<Group>>,
+    groups_by_name: HashMap<Arc<OsStr>, Arc<Group>>,
+}
+
+impl RootUsers {
+
+    /// Reads the users and groups tables under the given root directory.
+    /// A file that does not exist is treated as an empty table, as a
+    /// minimal image might not have one. Lines that can’t be read are
+    /// skipped, as the C library’s own files backend does.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error of kind `NotFound` if the root
+    /// directory itself does not exist, or is not a directory. It will
+    /// return `Err` if either file exists but can’t be read.
+    pub fn open<P: AsRef<Path>>(root: P) -> io::Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        if !fs::metadata(&root)?.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "root is not a directory"));
+        }
+
+        let passwd = or_empty_if_missing(PasswdFile::open(root.join("etc/passwd")))?;
+        let group = or_empty_if_missing(GroupFile::open(root.join("etc/group")))?;
+
+        let mut users = HashMap::new();
+        let mut users_by_name = HashMap::new();
+        for user in passwd.users() {
+            let user = Arc::new(user.clone());
+            users.entry(user.uid()).or_insert_with(|| Arc::clone(&user));
+            users_by_name.entry(Arc::clone(&user.name_arc)).or_insert(user);
+        }
+
+        let mut groups = HashMap::new();
+        let mut groups_by_name = HashMap::new();
+        for group in group.groups() {
+            let group = Arc::new(group.clone());
+            groups.entry(group.gid()).or_insert_with(|| Arc::clone(&group));
+            groups_by_name.entry(Arc::clone(&group.name_arc)).or_insert(group);
+        }
+
+        Ok(Self { root, users, users_by_name, groups, groups_by_name })
+    }
+
+    /// Returns the root directory that the tables were read from.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+fn or_empty_if_missing<T: Default>(result: io::Result<T>) -> io::Result<T> {
+    match result {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+        result => result,
+    }
+}
+
+impl Users for RootUsers {
+    fn get_user_by_uid(&self, uid: uid_t) -> Option<Arc<User>> {
+        self.users.get(&uid).cloned()
+    }
+
+    fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Option<Arc<User>> {
+        self.users_by_name.get(username.as_ref()).cloned()
+    }
+
+    fn get_current_uid(&self) -> uid_t {
+        get_current_uid()
+    }
+
+    fn get_current_username(&self) -> Option<Arc<OsStr>> {
+        self.users.get(&get_current_uid()).map(|u| Arc::clone(&u.name_arc))
+    }
+
+    fn get_effective_uid(&self) -> uid_t {
+        get_effective_uid()
+    }
+
+    fn get_effective_username(&self) -> Option<Arc<OsStr>> {
+        self.users.get(&get_effective_uid()).map(|u| Arc::clone(&u.name_arc))
+    }
+}
+
+impl Groups for RootUsers {
+    fn get_group_by_gid(&self, gid: gid_t) -> Option<Arc<Group>> {
+        self.groups.get(&gid).cloned()
+    }
+
+    fn get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Option<Arc<Group>> {
+        self.groups_by_name.get(group_name.as_ref()).cloned()
+    }
+
+    fn get_current_gid(&self) -> gid_t {
+        get_current_gid()
+    }
+
+    fn get_current_groupname(&self) -> Option<Arc<OsStr>> {
+        self.groups.get(&get_current_gid()).map(|g| Arc::clone(&g.name_arc))
+    }
+
+    fn get_effective_gid(&self) -> gid_t {
+        get_effective_gid()
+    }
+
+    fn get_effective_groupname(&self) -> Option<Arc<OsStr>> {
+        self.groups.get(&get_effective_gid()).map(|g| Arc::clone(&g.name_arc))
+    }
+
+    /// Like `getgrouplist`, this returns the given group first, followed by
+    /// every group that lists the user as a member, here in order of ID.
+    fn get_user_groups<S: AsRef<OsStr> + ?Sized>(&self, username: &S, gid: gid_t) -> Option<Vec<Arc<Group>>> {
+        let username = username.as_ref();
+        let mut others = self.groups.values()
+                             .filter(|g| g.gid() != gid && g.members().iter().any(|m| m == username))
+                             .cloned()
+                             .collect::<Vec<_>>();
+        others.sort_by_key(|g| g.gid());
+
+        Some(self.groups.get(&gid).cloned().into_iter().chain(others).collect())
+    }
+}
+
+
+/// An error that occurred while parsing a `passwd(5)` or `group(5)` file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    line: usize,
+    kind: ParseErrorKind,
+}
+
+/// The reason a line could not be parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseErrorKind {
+
+    /// The line had the wrong number of colon-separated fields.
+    FieldCount {
+
+        /// The number of fields that this type of file should have.
+        expected: usize,
+
+        /// The number of fields that the line actually had.
+        found: usize,
+    },
+
+    /// The name field was empty.
+    EmptyName,
+
+    /// The line started with whitespace, which would otherwise end up as
+    /// part of the name.
+    LeadingWhitespace,
+
+    /// A user or group ID field was not a valid number.
+    InvalidId(OsString),
+
+    /// Some other numeric field, such as one of the password aging fields
+    /// in a shadow file, was not a valid number.
+    InvalidNumber(OsString),
+}
+
+impl ParseError {
+
+    pub(crate) fn new(line: usize, kind: ParseErrorKind) -> Self {
+        Self { line, kind }
+    }
+
+    /// Returns the number of the line that could not be parsed, starting
+    /// from 1.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Returns the reason the line could not be parsed.
+    pub fn kind(&self) -> &ParseErrorKind {
+        &self.kind
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+
+        match self.kind {
+            ParseErrorKind::FieldCount { expected, found } => {
+                write!(f, "expected {} fields, found {}", expected, found)
+            }
+            ParseErrorKind::EmptyName => {
+                write!(f, "empty name")
+            }
+            ParseErrorKind::LeadingWhitespace => {
+                write!(f, "whitespace before the name")
+            }
+            ParseErrorKind::InvalidId(ref id) => {
+                write!(f, "invalid ID {:?}", id)
+            }
+            ParseErrorKind::InvalidNumber(ref number) => {
+                write!(f, "invalid number {:?}", number)
+            }
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+
+/// Splits a file into its lines, numbered from 1, separating each one from
+/// its line ending. A final newline does not produce an extra empty line.
+pub(crate) fn split_lines<'a>(input: &'a [u8]) -> Lines<'a> {
+    Lines { rest: input, number: 0 }
+}
+
+/// The iterator returned by `split_lines`.
+pub(crate) struct Lines<'a> {
+    rest: &'a [u8],
+    number: usize,
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = (usize, &'a [u8], LineEnding);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.is_empty() {
+            return None;
+        }
+
+        let (line, ending) = match self.rest.iter().position(|&b| b == b'\n') {
+            Some(end) => {
+                let line = &self.rest[.. end];
+                self.rest = &self.rest[end + 1 ..];
+
+                if line.ends_with(b"\r") { (&line[.. line.len() - 1], LineEnding::CrLf) }
+                                     else { (line, LineEnding::Lf) }
+            }
+            None => {
+                let line = self.rest;
+                self.rest = &[];
+                (line, LineEnding::None)
+            }
+        };
+
+        self.number += 1;
+        Some((self.number, line, ending))
+    }
+}
+
+/// Whether a line is a comment, a blank line, or a NIS compatibility line,
+/// rather than an entry.
+pub(crate) fn is_other_line(line: &[u8]) -> bool {
+    match line.iter().find(|b| !b.is_ascii_whitespace()) {
+        None                      => true,
+        Some(&b'#') | Some(&b'+') | Some(&b'-')  => true,
+        Some(_)                   => false,
+    }
+}
+
+/// Splits a line into its colon-separated fields, checking that there are
+/// the right number of them, and that the line doesn’t start with
+/// whitespace.
+pub(crate) fn split_fields(line: &[u8], expected: usize, number: usize) -> Result<Vec<&[u8]>, ParseError> {
+    if let Some(b) = line.first() {
+        if b.is_ascii_whitespace() {
+            return Err(ParseError { line: number, kind: ParseErrorKind::LeadingWhitespace });
+        }
+    }
+
+    let fields = line.split(|&b| b == b':').collect::<Vec<_>>();
+
+    if fields.len() == expected {
+        Ok(fields)
+    }
+    else {
+        let kind = ParseErrorKind::FieldCount { expected, found: fields.len() };
+        Err(ParseError { line: number, kind })
+    }
+}
+
+pub(crate) fn non_empty_name(field: &[u8], number: usize) -> Result<&OsStr, ParseError> {
+    if field.is_empty() {
+        Err(ParseError { line: number, kind: ParseErrorKind::EmptyName })
+    }
+    else {
+        Ok(OsStr::from_bytes(field))
+    }
+}
+
+pub(crate) fn parse_id(field: &[u8], number: usize) -> Result<uid_t, ParseError> {
+    let id = std::str::from_utf8(field).ok()
+                 .filter(|s| s.bytes().all(|b| b.is_ascii_digit()))
+                 .and_then(|s| s.parse::<uid_t>().ok());
+
+    match id {
+        Some(id) => Ok(id),
+        None     => Err(ParseError { line: number, kind: ParseErrorKind::InvalidId(bytes_to_os(field)) }),
+    }
+}
+
+pub(crate) fn bytes_to_os(bytes: &[u8]) -> OsString {
+    OsString::from_vec(bytes.to_vec())
+}
+
+/// Reads a line of a `passwd(5)` file as a user.
+fn parse_user(line: &[u8], number: usize) -> Result<User, ParseError> {
+    let fields = split_fields(line, 7, number)?;
+    let name = non_empty_name(fields[0], number)?;
+    let uid = parse_id(fields[2], number)?;
+    let gid = parse_id(fields[3], number)?;
+
+    Ok(User::new(uid, name, gid)
+        .with_password(OsStr::from_bytes(fields[1]))
+        .with_home_dir(OsStr::from_bytes(fields[5]))
+        .with_shell(OsStr::from_bytes(fields[6]))
+        .with_gecos(OsStr::from_bytes(fields[4])))
+}
+
+/// Reads a line of a `group(5)` file as a group and its password field.
+fn parse_group(line: &[u8], number: usize) -> Result<(Group, OsString), ParseError> {
+    let fields = split_fields(line, 4, number)?;
+    let name = non_empty_name(fields[0], number)?;
+    let gid = parse_id(fields[2], number)?;
+
+    let mut group = Group::new(gid, name);
+    for member in fields[3].split(|&b| b == b',').filter(|m| !m.is_empty()) {
+        group = group.add_member(OsStr::from_bytes(member));
+    }
+
+    Ok((group, bytes_to_os(fields[1])))
+}
+
+/// Writes a list of fields separated by colons, refusing any field that
+/// would break the line apart. A carriage return at the end of a line would
+/// be read back as part of a `\r\n` line ending, so it’s refused too.
+fn write_fields<W: Write>(writer: &mut W, fields: &[&[u8]]) -> io::Result<()> {
+    if fields.iter().any(|f| f.contains(&b':') || f.contains(&b'\n') || f.contains(&b'\r')) {
+        return Err(invalid_field());
+    }
+
+    writer.write_all(&fields.join(&b':'))
+}
+
+fn invalid_field() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "field contains a separator character")
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_util::TempDir;
+
+    const PASSWD: &str = "\
+# System accounts
+root:x:0:0:root:/root:/bin/bash
+daemon:x:1:1:daemon:/usr/sbin:/usr/sbin/nologin
+
++@netgroup
+fred:$6$salt$hash:1000:100:Fred Bloggs,,,:/home/fred:/bin/zsh
+";
+
+    const GROUP: &str = "\
+root:x:0:
+# Admins
+wheel:x:10:fred,jim
+users::100:
+-badgroup
+";
+
+    #[test]
+    fn passwd_users() {
+        let passwd = PasswdFile::parse(PASSWD);
+        let users = passwd.users().collect::<Vec<_>>();
+
+        assert_eq!(users.len(), 3);
+        assert_eq!(users[2].uid(), 1000);
+        assert_eq!(users[2].name(), "fred");
+        assert_eq!(users[2].primary_group_id(), 100);
+        assert_eq!(users[2].password(), "$6$salt$hash");
+        assert_eq!(users[2].home_dir(), Path::new("/home/fred"));
+        assert_eq!(users[2].shell(), Path::new("/bin/zsh"));
+        assert_eq!(users[2].gecos(), "Fred Bloggs,,,");
+    }
+
+    #[test]
+    fn passwd_round_trip() {
+        let passwd = PasswdFile::parse(PASSWD);
+        assert_eq!(passwd.lines().len(), 6);
+        assert_eq!(passwd.to_bytes(), PASSWD.as_bytes());
+    }
+
+    #[test]
+    fn passwd_add_user() {
+        let mut passwd = PasswdFile::parse("# header\n");
+        passwd.add_user(User::new(5, "new", 5).with_password("x").with_shell("/bin/sh").with_home_dir("/"));
+        assert_eq!(passwd.to_bytes(), b"# header\nnew:x:5:5::/:/bin/sh\n");
+    }
+
+    #[test]
+    fn passwd_invalid_field() {
+        let mut passwd = PasswdFile::new();
+        passwd.add_user(User::new(5, "bad:name", 5));
+        assert_eq!(passwd.write_to(&mut Vec::new()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let mut passwd = PasswdFile::new();
+        passwd.add_user(User::new(5, "fred", 5).with_shell("/bin/sh\r"));
+        assert_eq!(passwd.write_to(&mut Vec::new()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn passwd_invalid_lines_round_trip() {
+        let input = "root:x:0:0:root:/root:/bin/sh\r\nbad:x:1\r\n:x:2:2::/:/bin/sh\nfred:x:1000:100::/home/fred:/bin/sh";
+        let passwd = PasswdFile::parse(input);
+        assert_eq!(passwd.users().count(), 2);
+        assert_eq!(passwd.errors().map(|e| e.line()).collect::<Vec<_>>(), vec![ 2, 3 ]);
+        assert_eq!(passwd.to_bytes(), input.as_bytes());
+    }
+
+    #[test]
+    fn passwd_field_count() {
+        let passwd = PasswdFile::parse("root:x:0:0:root:/root:/bin/bash\nbad:x:1\n");
+        let error = passwd.errors().next().unwrap();
+        assert_eq!(error.line(), 2);
+        assert_eq!(error.kind(), &ParseErrorKind::FieldCount { expected: 7, found: 3 });
+        assert_eq!(error.to_string(), "line 2: expected 7 fields, found 3");
+    }
+
+    #[test]
+    fn passwd_invalid_uid() {
+        let passwd = PasswdFile::parse("\n\nroot:x:-1:0::/:/bin/sh");
+        let error = passwd.errors().next().unwrap();
+        assert_eq!(error.line(), 3);
+        assert_eq!(error.kind(), &ParseErrorKind::InvalidId(OsString::from("-1")));
+    }
+
+    #[test]
+    fn passwd_empty_name() {
+        let passwd = PasswdFile::parse(":x:0:0::/:/bin/sh");
+        let error = passwd.errors().next().unwrap();
+        assert_eq!(error.kind(), &ParseErrorKind::EmptyName);
+    }
+
+    #[test]
+    fn passwd_empty() {
+        assert!(PasswdFile::parse("").lines().is_empty());
+    }
+
+    #[test]
+    fn group_members() {
+        let group = GroupFile::parse(GROUP);
+        let groups = group.groups().collect::<Vec<_>>();
+
+        assert_eq!(groups.len(), 3);
+        assert!(groups[0].members().is_empty());
+        assert_eq!(groups[1].gid(), 10);
+        assert_eq!(groups[1].members(), &[OsString::from("fred"), OsString::from("jim")]);
+    }
+
+    #[test]
+    fn group_invalid_lines_round_trip() {
+        let input = "root:x:0:\nwheel:x:ten:fred\nusers::100:fred\n";
+        let group = GroupFile::parse(input);
+        assert_eq!(group.groups().count(), 2);
+        assert_eq!(group.errors().next().unwrap().kind(), &ParseErrorKind::InvalidId(OsString::from("ten")));
+        assert_eq!(group.to_bytes(), input.as_bytes());
+    }
+
+    #[test]
+    fn group_round_trip() {
+        let group = GroupFile::parse(GROUP);
+        assert_eq!(group.to_bytes(), GROUP.as_bytes());
+    }
+
+    #[test]
+    fn group_field_count() {
+        let group = GroupFile::parse("root:x:0:\nwheel:x:10:a:b\n");
+        let error = group.errors().next().unwrap();
+        assert_eq!(error.line(), 2);
+        assert_eq!(error.kind(), &ParseErrorKind::FieldCount { expected: 4, found: 5 });
+    }
+
+    fn temp_root(name: &str, passwd: Option<&str>, group: Option<&str>) -> TempDir {
+        let root = TempDir::new(&format!("root-{}", name));
+
+        if let Some(passwd) = passwd {
+            root.write("etc/passwd", passwd);
+        }
+        if let Some(group) = group {
+            root.write("etc/group", group);
+        }
+
+        root
+    }
+
+    #[test]
+    fn root_users() {
+        let root = temp_root("lookups", Some(PASSWD), Some(GROUP));
+        let users = RootUsers::open(root.path()).unwrap();
+
+        assert_eq!(users.root(), root.path());
+        assert_eq!(users.get_user_by_uid(1000).map(|u| Arc::clone(&u.name_arc)), Some(Arc::from(OsStr::new("fred"))));
+        assert_eq!(users.get_user_by_name("daemon").map(|u| u.uid()), Some(1));
+        assert_eq!(users.get_group_by_name("wheel").map(|g| g.gid()), Some(10));
+        assert!(users.get_group_by_gid(1000).is_none());
+    }
+
+    #[test]
+    fn root_users_memberships() {
+        let root = temp_root("memberships", Some(PASSWD), Some(GROUP));
+        let users = RootUsers::open(root.path()).unwrap();
+
+        let gids = users.get_user_groups("fred", 100).unwrap().iter().map(|g| g.gid()).collect::<Vec<_>>();
+        assert_eq!(gids, vec![100, 10]);
+
+        let fred = users.get_user_by_name("fred").unwrap();
+        assert!(users.is_member(&fred, 10));
+        assert!(!users.is_member(&fred, 0));
+    }
+
+    #[test]
+    fn root_users_first_entry_wins() {
+        let root = temp_root("duplicates", Some("a:x:5:5::/:/bin/sh\nb:x:5:5::/:/bin/sh\na:x:6:6::/:/bin/sh\n"), None);
+        let users = RootUsers::open(root.path()).unwrap();
+
+        assert_eq!(users.get_user_by_uid(5).map(|u| Arc::clone(&u.name_arc)), Some(Arc::from(OsStr::new("a"))));
+        assert_eq!(users.get_user_by_name("a").map(|u| u.uid()), Some(5));
+        assert_eq!(users.get_user_by_uid(6).map(|u| Arc::clone(&u.name_arc)), Some(Arc::from(OsStr::new("a"))));
+    }
+
+    #[test]
+    fn root_users_missing_files() {
+        let root = temp_root("missing", None, None);
+        let users = RootUsers::open(root.path()).unwrap();
+        assert!(users.get_user_by_uid(0).is_none());
+        assert!(users.get_group_by_gid(0).is_none());
+    }
+
+    #[test]
+    fn root_users_missing_root() {
+        let root = temp_root("missing-root", None, None);
+        let error = RootUsers::open(root.path().join("no-such-image")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+
+        root.write("image", "");
+        let error = RootUsers::open(root.path().join("image")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn root_users_skips_invalid_lines() {
+        let root = temp_root("invalid", Some("root:x:0\nfred:x:1000:100::/home/fred:/bin/sh\n"), None);
+        let users = RootUsers::open(root.path()).unwrap();
+        assert!(users.get_user_by_name("root").is_none());
+        assert_eq!(users.get_user_by_name("fred").map(|u| u.uid()), Some(1000));
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        let passwd = PasswdFile::parse("root:x:0:0:root:/root:/bin/sh\r\n");
+        assert_eq!(passwd.users().next().unwrap().shell(), Path::new("/bin/sh"));
+    }
+
+    #[test]
+    fn crlf_round_trip() {
+        assert_eq!(PasswdFile::parse("# comment\r\nroot:x:0:0:root:/root:/bin/sh\r\n").to_bytes(),
+                   "# comment\r\nroot:x:0:0:root:/root:/bin/sh\r\n".as_bytes());
+        assert_eq!(GroupFile::parse("root:x:0:\r\n\r\nwheel:x:10:\n").to_bytes(),
+                   "root:x:0:\r\n\r\nwheel:x:10:\n".as_bytes());
+    }
+
+    #[test]
+    fn no_trailing_newline_round_trip() {
+        let passwd = PasswdFile::parse("# header\nroot:x:0:0:root:/root:/bin/sh");
+        assert_eq!(passwd.to_bytes(), "# header\nroot:x:0:0:root:/root:/bin/sh".as_bytes());
+
+        let group = GroupFile::parse("root:x:0:\n# footer");
+        assert_eq!(group.to_bytes(), "root:x:0:\n# footer".as_bytes());
+    }
+
+    #[test]
+    fn add_after_no_trailing_newline() {
+        let mut passwd = PasswdFile::parse("root:x:0:0:root:/root:/bin/sh");
+        passwd.add_user(User::new(5, "new", 5).with_password("x").with_shell("/bin/sh").with_home_dir("/"));
+        assert_eq!(passwd.to_bytes(), "root:x:0:0:root:/root:/bin/sh\nnew:x:5:5::/:/bin/sh\n".as_bytes());
+    }
+
+    #[test]
+    fn lone_newline_round_trip() {
+        let passwd = PasswdFile::parse("\n");
+        assert_eq!(passwd.lines().len(), 1);
+        assert_eq!(passwd.to_bytes(), b"\n");
+    }
+
+    #[test]
+    fn leading_whitespace() {
+        let passwd = PasswdFile::parse("root:x:0:0:root:/root:/bin/sh\n root:x:0:0:root:/root:/bin/sh\n");
+        let error = passwd.errors().next().unwrap();
+        assert_eq!(error.line(), 2);
+        assert_eq!(error.kind(), &ParseErrorKind::LeadingWhitespace);
+
+        let group = GroupFile::parse("\twheel:x:10:\n");
+        let error = group.errors().next().unwrap();
+        assert_eq!(error.kind(), &ParseErrorKind::LeadingWhitespace);
+
+        // Indented comments and lines of only whitespace are still fine.
+        assert_eq!(GroupFile::parse("  # comment\n \t\n").to_bytes(), b"  # comment\n \t\n");
+    }
+}
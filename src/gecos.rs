@@ -0,0 +1,157 @@
+//! Splitting the GECOS field into its parts.
+//!
+//! The GECOS field of a `passwd` entry is free text, but `chfn` and
+//! `finger` treat it as a comma-separated list of the user’s full name,
+//! room number, work phone number, home phone number, and anything else.
+//! Any `&` in the full name stands for the username with its first letter
+//! capitalised.
+//!
+//! ## Example
+//!
+//! ```
+//! use users::User;
+//! use users::gecos::Gecos;
+//! use users::os::unix::UserExt;
+//!
+//! let user = User::new(1000, "fred", 1000).with_gecos("& Bloggs,42,555-1234,,");
+//! let gecos = Gecos::from_user(&user);
+//! assert_eq!(gecos.full_name(), "Fred Bloggs");
+//! assert_eq!(gecos.room(), "42");
+//! assert_eq!(gecos.work_phone(), "555-1234");
+//! assert_eq!(gecos.home_phone(), "");
+//! ```
+
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+use base::User;
+use base::os::unix::UserExt;
+
+
+/// The parts of a GECOS field. Parts that are missing from the field are
+/// empty.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Gecos {
+    full_name: OsString,
+    room: OsString,
+    work_phone: OsString,
+    home_phone: OsString,
+    other: OsString,
+}
+
+impl Gecos {
+
+    /// Splits a GECOS field into its parts, replacing any `&` in the full
+    /// name with the capitalised form of the given username.
+    pub fn parse<G, N>(gecos: &G, username: &N) -> Self
+    where G: AsRef<OsStr> + ?Sized,
+          N: AsRef<OsStr> + ?Sized,
+    {
+        let mut parts = gecos.as_ref().as_bytes().splitn(5, |&b| b == b',');
+        let mut next = || OsString::from_vec(parts.next().unwrap_or_default().to_vec());
+
+        let full_name = expand_ampersand(&next(), username.as_ref());
+        Self {
+            full_name,
+            room:       next(),
+            work_phone: next(),
+            home_phone: next(),
+            other:      next(),
+        }
+    }
+
+    /// Splits the given user’s GECOS field into its parts.
+    pub fn from_user(user: &User) -> Self {
+        Self::parse(user.gecos(), user.name())
+    }
+
+    /// Returns the user’s full name, with any `&` expanded.
+    pub fn full_name(&self) -> &OsStr {
+        &self.full_name
+    }
+
+    /// Returns the user’s room number or office.
+    pub fn room(&self) -> &OsStr {
+        &self.room
+    }
+
+    /// Returns the user’s work phone number.
+    pub fn work_phone(&self) -> &OsStr {
+        &self.work_phone
+    }
+
+    /// Returns the user’s home phone number.
+    pub fn home_phone(&self) -> &OsStr {
+        &self.home_phone
+    }
+
+    /// Returns everything after the home phone number, which may itself
+    /// contain commas.
+    pub fn other(&self) -> &OsStr {
+        &self.other
+    }
+}
+
+/// Replaces each `&` in a full name with the username, with its first
+/// letter capitalised.
+fn expand_ampersand(full_name: &OsStr, username: &OsStr) -> OsString {
+    let full_name = full_name.as_bytes();
+    if ! full_name.contains(&b'&') {
+        return OsString::from_vec(full_name.to_vec());
+    }
+
+    let mut capitalised = username.as_bytes().to_vec();
+    if let Some(first) = capitalised.first_mut() {
+        first.make_ascii_uppercase();
+    }
+
+    let mut expanded = Vec::with_capacity(full_name.len() + capitalised.len());
+    for &b in full_name {
+        if b == b'&' { expanded.extend_from_slice(&capitalised) }
+                else { expanded.push(b) }
+    }
+
+    OsString::from_vec(expanded)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn all_parts() {
+        let gecos = Gecos::parse("Fred Bloggs,Room 101,555-1234,555-9876,fred@example.com,extra", "fred");
+        assert_eq!(gecos.full_name(), "Fred Bloggs");
+        assert_eq!(gecos.room(), "Room 101");
+        assert_eq!(gecos.work_phone(), "555-1234");
+        assert_eq!(gecos.home_phone(), "555-9876");
+        assert_eq!(gecos.other(), "fred@example.com,extra");
+    }
+
+    #[test]
+    fn name_only() {
+        let gecos = Gecos::parse("Fred Bloggs", "fred");
+        assert_eq!(gecos.full_name(), "Fred Bloggs");
+        assert_eq!(gecos.room(), "");
+        assert_eq!(gecos.other(), "");
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(Gecos::parse("", "fred"), Gecos::default());
+    }
+
+    #[test]
+    fn ampersand() {
+        assert_eq!(Gecos::parse("& Bloggs", "fred").full_name(), "Fred Bloggs");
+        assert_eq!(Gecos::parse("&&", "jo").full_name(), "JoJo");
+        assert_eq!(Gecos::parse("System,&", "daemon").room(), "&");
+    }
+
+    #[test]
+    fn from_user() {
+        let user = User::new(0, "root", 0).with_gecos("&,,,");
+        assert_eq!(Gecos::from_user(&user).full_name(), "Root");
+    }
+}
@@ -57,6 +57,13 @@
 //! function, as it’s such a common operation that it deserves special
 //! treatment.
 //!
+//! A lookup can also fail without the user being missing: the C library may
+//! run out of file descriptors, or a network-backed database may be down.
+//! The `get_*` functions treat these failures as the user not existing; if
+//! you need to tell the two apart, use the `try_get_*` variants, such as
+//! [`try_get_user_by_uid`](fn.try_get_user_by_uid.html), which return a
+//! [`LookupError`](struct.LookupError.html) instead.
+//!
 //!
 //! ## Caching
 //!
@@ -83,10 +90,11 @@
 //! println!("Hello again, {}!", user.name().to_string_lossy());
 //! ```
 //!
-//! This cache is **only additive**: it’s not possible to drop it, or erase
-//! selected entries, as when the database may have been modified, it’s best to
-//! start entirely afresh. So to accomplish this, just start using a new
-//! `UsersCache`.
+//! By default, this cache holds on to every result forever. Programs that run
+//! for long enough to see the database change can give the cached entries a
+//! time-to-live, or remove them with `invalidate_user`, `invalidate_group`,
+//! or `clear`. See the [`cache` module documentation](cache/index.html) for
+//! more.
 //!
 //!
 //! ## Groups
@@ -114,6 +122,14 @@
 //! record all interactions with the operating system.
 //!
 //!
+//! ## Passwords
+//!
+//! The [`password`](password/index.html) module, which checks passwords
+//! against `crypt(3)` hashes, is behind the `password` feature, which is
+//! off by default. It links against the system’s `libcrypt`, which not
+//! every program wants to depend on.
+//!
+//!
 //! ## Caveats
 //!
 //! You should be prepared for the users and groups tables to be completely
@@ -131,23 +147,47 @@ mod base;
 pub use base::{User, Group, os};
 pub use base::{get_user_by_uid, get_user_by_name};
 pub use base::{get_group_by_gid, get_group_by_name};
+pub use base::{try_get_user_by_uid, try_get_user_by_name};
+pub use base::{try_get_group_by_gid, try_get_group_by_name, LookupError};
 pub use base::{get_current_uid, get_current_username};
 pub use base::{get_effective_uid, get_effective_username};
 pub use base::{get_current_gid, get_current_groupname};
 pub use base::{get_effective_gid, get_effective_groupname};
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd", target_os = "dragonfly", target_os = "openbsd"))]
+pub use base::{get_resuid, get_resgid, get_saved_uid, get_saved_gid};
 pub use base::{get_user_groups, group_access_list};
-pub use base::{all_users};
+pub use base::{all_users, all_groups, AllUsers, AllGroups};
+pub use base::{list_all_users, list_all_groups, lock_all_users, lock_all_groups};
 
 #[cfg(feature = "cache")]
 pub mod cache;
 
 #[cfg(feature = "cache")]
-pub use cache::UsersCache;
+pub use cache::{UsersCache, SyncUsersCache};
 
 #[cfg(feature = "mock")]
 pub mod mock;
 
+#[cfg(target_os = "linux")]
+pub mod caps;
+
+pub mod command;
+
+pub mod files;
+
+pub mod gecos;
+
+pub mod shadow;
+
+pub mod expiry;
+
+#[cfg(all(feature = "password", not(target_os = "android")))]
+pub mod password;
+
 pub mod switch;
 
 mod traits;
-pub use traits::{Users, Groups};
+pub use traits::{Users, Groups, DynUsers, DynGroups};
+
+#[cfg(test)]
+mod test_util;
@@ -29,6 +29,68 @@
 //!
 //! The exports get re-exported into the mock module, for simpler `use` lines.
 //!
+//! To test code that behaves differently depending on who it’s running as,
+//! the real and effective user and group IDs can be set separately, and
+//! users can be made members of groups:
+//!
+//! ```
+//! use users::mock::{MockUsers, User, Group};
+//! use users::{Users, Groups};
+//!
+//! let users = MockUsers::with_current_uid(1000)
+//!     .with_effective_uid(0)
+//!     .with_current_gid(100)
+//!     .with_effective_gid(0)
+//!     .with_user(User::new(1000, "bobbins", 100))
+//!     .with_group(Group::new(100, "users"))
+//!     .with_group(Group::new(27, "sudo"))
+//!     .with_member("bobbins", 27);
+//!
+//! assert_eq!(users.get_effective_uid(), 0);
+//! assert_eq!(users.get_current_gid(), 100);
+//!
+//! let bobbins = users.get_user_by_uid(1000).unwrap();
+//! assert!(users.is_member(&bobbins, 27));
+//! assert_eq!(users.get_user_groups("bobbins", 100).unwrap().len(), 2);
+//! ```
+//!
+//! Fixture users and groups can also be read from text in the
+//! `passwd(5)` and `group(5)` formats, or from files in those formats:
+//!
+//! ```
+//! use users::mock::MockUsers;
+//! use users::{Users, Groups};
+//!
+//! let passwd = "root:x:0:0:root:/root:/bin/sh\nfred:x:1000:100::/home/fred:/bin/sh\n";
+//! let group = "root:x:0:\nusers:x:100:\nsudo:x:27:fred\n";
+//!
+//! let users = MockUsers::from_passwd_str(passwd, group).unwrap()
+//!     .with_current_user(1000);
+//! assert_eq!(users.get_current_username().unwrap().to_str(), Some("fred"));
+//! assert!(users.is_member(&users.get_user_by_name("fred").unwrap(), 27));
+//! ```
+//!
+//!
+//! ## Simulating Failures
+//!
+//! Lookups of particular users and groups can be made to fail, either every
+//! time or for a number of attempts, or to be slow. Every call to a `Users`
+//! or `Groups` method is recorded, so tests can check which lookups their
+//! code made:
+//!
+//! ```
+//! use users::mock::{MockUsers, MockLookup, MockCall, User, LookupError};
+//! use users::Users;
+//!
+//! let mut users = MockUsers::with_current_uid(1000);
+//! users.add_user(User::new(1000, "fred", 100));
+//! users.fail_lookup_times(MockLookup::Uid(1000), LookupError::new("getpwuid_r", 5), 1);
+//!
+//! assert!(users.try_get_user_by_uid(1000).is_err());
+//! assert!(users.try_get_user_by_uid(1000).unwrap().is_some());
+//! assert_eq!(users.calls(), vec![ MockCall::TryGetUserByUid(1000), MockCall::TryGetUserByUid(1000) ]);
+//! ```
+//!
 //!
 //! ## Using Mock Users
 //!
@@ -57,11 +119,18 @@
 //! ```
 
 use std::collections::HashMap;
-use std::ffi::OsStr;
-use std::sync::Arc;
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use base::os::unix::GroupExt;
+use files::{PasswdFile, GroupFile, ParseError};
 
 pub use libc::{uid_t, gid_t};
-pub use base::{User, Group};
+pub use base::{User, Group, LookupError};
 pub use traits::{Users, Groups};
 
 
@@ -70,18 +139,148 @@ pub struct MockUsers {
     users: HashMap<uid_t, Arc<User>>,
     groups: HashMap<gid_t, Arc<Group>>,
     uid: uid_t,
+    euid: uid_t,
+    gid: gid_t,
+    egid: gid_t,
+    failures: Mutex<HashMap<MockLookup, MockFailure>>,
+    delays: HashMap<MockLookup, Duration>,
+    calls: Mutex<Vec<MockCall>>,
 }
 
 
 impl MockUsers {
 
     /// Create a new, empty mock users table.
+    ///
+    /// The given ID is used as the current and effective user ID, and as
+    /// the current and effective group ID, until they are changed with the
+    /// `with_*` methods.
     pub fn with_current_uid(current_uid: uid_t) -> Self {
         Self {
             users: HashMap::new(),
             groups: HashMap::new(),
             uid: current_uid,
+            euid: current_uid,
+            gid: current_uid,
+            egid: current_uid,
+            failures: Mutex::new(HashMap::new()),
+            delays: HashMap::new(),
+            calls: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Creates a mock users table from the contents of a `passwd(5)` file
+    /// and a `group(5)` file. When two entries share an ID, the first one
+    /// wins, as it would with the real databases.
+    ///
+    /// The current and effective IDs are all 0, until they are changed with
+    /// [`with_current_user`](#method.with_current_user) or the other
+    /// `with_*` methods.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` with the first line that can’t be
+    /// parsed, if there is one, as a fixture with a broken entry is
+    /// almost always a mistake.
+    pub fn from_passwd_str<P, G>(passwd: &P, group: &G) -> Result<Self, ParseError>
+    where P: AsRef<[u8]> + ?Sized,
+          G: AsRef<[u8]> + ?Sized,
+    {
+        Self::from_tables(&PasswdFile::parse(passwd), &GroupFile::parse(group))
+    }
+
+    /// Creates a mock users table from the `passwd(5)` and `group(5)` files
+    /// at the given paths, in the same way as
+    /// [`from_passwd_str`](#method.from_passwd_str).
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` if either file can’t be read, or with
+    /// an error of kind `InvalidData` if either can’t be parsed.
+    pub fn from_passwd_file<P, G>(passwd: P, group: G) -> io::Result<Self>
+    where P: AsRef<Path>,
+          G: AsRef<Path>,
+    {
+        let passwd = PasswdFile::open(passwd)?;
+        let group = GroupFile::open(group)?;
+        Self::from_tables(&passwd, &group).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn from_tables(passwd: &PasswdFile, group: &GroupFile) -> Result<Self, ParseError> {
+        if let Some(error) = passwd.errors().chain(group.errors()).next() {
+            return Err(error.clone());
+        }
+
+        let mut mock = Self::with_current_uid(0);
+
+        for user in passwd.users() {
+            mock.users.entry(user.uid()).or_insert_with(|| Arc::new(user.clone()));
+        }
+
+        for group in group.groups() {
+            mock.groups.entry(group.gid()).or_insert_with(|| Arc::new(group.clone()));
+        }
+
+        Ok(mock)
+    }
+
+    /// Makes the user with the given ID the current and effective user,
+    /// and their primary group the current and effective group, if they
+    /// are in the table.
+    pub fn with_current_user(mut self, uid: uid_t) -> Self {
+        self.uid = uid;
+        self.euid = uid;
+
+        if let Some(gid) = self.users.get(&uid).map(|u| u.primary_group_id()) {
+            self.gid = gid;
+            self.egid = gid;
+        }
+
+        self
+    }
+
+    /// Sets the effective user ID, for code that checks whether it’s
+    /// running setuid.
+    pub fn with_effective_uid(mut self, uid: uid_t) -> Self {
+        self.euid = uid;
+        self
+    }
+
+    /// Sets the current (real) group ID.
+    pub fn with_current_gid(mut self, gid: gid_t) -> Self {
+        self.gid = gid;
+        self
+    }
+
+    /// Sets the effective group ID.
+    pub fn with_effective_gid(mut self, gid: gid_t) -> Self {
+        self.egid = gid;
+        self
+    }
+
+    /// Adds a user to the users table.
+    pub fn with_user(mut self, user: User) -> Self {
+        self.add_user(user);
+        self
+    }
+
+    /// Adds a group to the groups table.
+    pub fn with_group(mut self, group: Group) -> Self {
+        self.add_group(group);
+        self
+    }
+
+    /// Makes the user with the given name a member of the group with the
+    /// given ID, which must already be in the table.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if there is no group with that ID.
+    pub fn with_member<S: AsRef<OsStr> + ?Sized>(mut self, username: &S, gid: gid_t) -> Self {
+        if ! self.add_member(username, gid) {
+            panic!("No mock group with ID {}", gid);
         }
+        self
     }
 
     /// Add a user to the users table.
@@ -93,71 +292,306 @@ impl MockUsers {
     pub fn add_group(&mut self, group: Group) -> Option<Arc<Group>> {
         self.groups.insert(group.gid(), Arc::new(group))
     }
+
+    /// Adds the user with the given name to the member list of the group
+    /// with the given ID. Returns `false` if there is no such group.
+    pub fn add_member<S: AsRef<OsStr> + ?Sized>(&mut self, username: &S, gid: gid_t) -> bool {
+        let group = match self.groups.get_mut(&gid) {
+            Some(g) => Arc::make_mut(g),
+            None    => return false,
+        };
+
+        let username = username.as_ref();
+        if ! group.members().iter().any(|m| m == username) {
+            *group = group.clone().add_member(username);
+        }
+        true
+    }
+
+    /// Makes every lookup of the given user or group fail with the given
+    /// error, until [`clear_failures`](#method.clear_failures) is called.
+    ///
+    /// The `get_*` methods return `None` when a lookup fails, and the
+    /// `try_get_*` methods return the error. When a `Memberships` lookup
+    /// fails, `get_user_groups` returns `None` and `is_member` returns
+    /// `false`, unless the group is the user’s primary group.
+    pub fn fail_lookup(&mut self, lookup: MockLookup, error: LookupError) {
+        self.failure_mut().insert(lookup, MockFailure { error, remaining: None });
+    }
+
+    /// Makes the next `times` lookups of the given user or group fail with
+    /// the given error, after which they succeed again, to simulate a name
+    /// service that fails intermittently.
+    pub fn fail_lookup_times(&mut self, lookup: MockLookup, error: LookupError, times: usize) {
+        self.failure_mut().insert(lookup, MockFailure { error, remaining: Some(times) });
+    }
+
+    /// Removes every failure set up with `fail_lookup` or
+    /// `fail_lookup_times`.
+    pub fn clear_failures(&mut self) {
+        self.failure_mut().clear();
+    }
+
+    /// Makes every lookup of the given user or group sleep for the given
+    /// duration before returning, to simulate a slow name service.
+    pub fn delay_lookup(&mut self, lookup: MockLookup, delay: Duration) {
+        self.delays.insert(lookup, delay);
+    }
+
+    /// Returns every `Users` and `Groups` method that has been called on
+    /// this table, in order.
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.calls.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    /// Forgets the calls made so far.
+    pub fn clear_calls(&self) {
+        self.calls.lock().unwrap_or_else(PoisonError::into_inner).clear();
+    }
+
+    fn record(&self, call: MockCall) {
+        self.calls.lock().unwrap_or_else(PoisonError::into_inner).push(call);
+    }
+
+    fn failure_mut(&mut self) -> &mut HashMap<MockLookup, MockFailure> {
+        self.failures.get_mut().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Sleeps for the lookup’s delay, if it has one, and then returns its
+    /// failure, if it has one that hasn’t run out.
+    fn script(&self, lookup: MockLookup) -> Result<(), LookupError> {
+        if let Some(&delay) = self.delays.get(&lookup) {
+            thread::sleep(delay);
+        }
+
+        let mut failures = self.failures.lock().unwrap_or_else(PoisonError::into_inner);
+        match failures.get_mut(&lookup) {
+            None => return Ok(()),
+            Some(failure) => match failure.remaining {
+                Some(0)         => {},
+                Some(ref mut n) => { *n -= 1; return Err(failure.error) },
+                None            => return Err(failure.error),
+            },
+        }
+
+        // The failure has run out, so forget about it.
+        failures.remove(&lookup);
+        Ok(())
+    }
+
+    fn find_user_by_name(&self, username: &OsStr) -> Option<&Arc<User>> {
+        self.users.values().find(|u| u.name() == username)
+    }
+
+    fn lookup_user_by_uid(&self, uid: uid_t) -> Result<Option<Arc<User>>, LookupError> {
+        self.script(MockLookup::Uid(uid))?;
+        Ok(self.users.get(&uid).cloned())
+    }
+
+    fn lookup_user_by_name(&self, username: &OsStr) -> Result<Option<Arc<User>>, LookupError> {
+        self.script(MockLookup::Username(username.into()))?;
+        Ok(self.find_user_by_name(username).cloned())
+    }
+
+    fn lookup_group_by_gid(&self, gid: gid_t) -> Result<Option<Arc<Group>>, LookupError> {
+        self.script(MockLookup::Gid(gid))?;
+        Ok(self.groups.get(&gid).cloned())
+    }
+
+    fn lookup_group_by_name(&self, group_name: &OsStr) -> Result<Option<Arc<Group>>, LookupError> {
+        self.script(MockLookup::GroupName(group_name.into()))?;
+        Ok(self.groups.values().find(|g| g.name() == group_name).cloned())
+    }
+}
+
+
+/// A user or group lookup that can be made to fail or be slow.
+///
+/// Lookups by ID match the `get_*_by_uid` and `get_*_by_gid` methods, and
+/// the methods that return the current or effective user or group; lookups
+/// by name match the `get_*_by_name` methods; and lookups of memberships
+/// match `get_user_groups` and `is_member`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MockLookup {
+
+    /// Looking up the user with the given ID.
+    Uid(uid_t),
+
+    /// Looking up the user with the given name.
+    Username(OsString),
+
+    /// Looking up the group with the given ID.
+    Gid(gid_t),
+
+    /// Looking up the group with the given name.
+    GroupName(OsString),
+
+    /// Looking up the groups that the user with the given name is a
+    /// member of.
+    Memberships(OsString),
+}
+
+/// A call to one of the `Users` or `Groups` methods of a `MockUsers`, along
+/// with its argument, if it has one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum MockCall {
+    GetUserByUid(uid_t),
+    GetUserByName(OsString),
+    TryGetUserByUid(uid_t),
+    TryGetUserByName(OsString),
+    GetCurrentUid,
+    GetCurrentUsername,
+    GetEffectiveUid,
+    GetEffectiveUsername,
+    GetGroupByGid(gid_t),
+    GetGroupByName(OsString),
+    TryGetGroupByGid(gid_t),
+    TryGetGroupByName(OsString),
+    GetCurrentGid,
+    GetCurrentGroupname,
+    GetEffectiveGid,
+    GetEffectiveGroupname,
+    GetUserGroups(OsString, gid_t),
+    IsMember(OsString, gid_t),
+}
+
+struct MockFailure {
+    error: LookupError,
+    remaining: Option<usize>,
 }
 
 
 impl Users for MockUsers {
     fn get_user_by_uid(&self, uid: uid_t) -> Option<Arc<User>> {
-        self.users.get(&uid).cloned()
+        self.record(MockCall::GetUserByUid(uid));
+        self.lookup_user_by_uid(uid).ok()?
     }
 
     fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Option<Arc<User>> {
-        self.users.values().find(|u| u.name() == username.as_ref()).cloned()
+        self.record(MockCall::GetUserByName(username.into()));
+        self.lookup_user_by_name(username.as_ref()).ok()?
+    }
+
+    fn try_get_user_by_uid(&self, uid: uid_t) -> Result<Option<Arc<User>>, LookupError> {
+        self.record(MockCall::TryGetUserByUid(uid));
+        self.lookup_user_by_uid(uid)
+    }
+
+    fn try_get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Result<Option<Arc<User>>, LookupError> {
+        self.record(MockCall::TryGetUserByName(username.into()));
+        self.lookup_user_by_name(username.as_ref())
     }
 
     fn get_current_uid(&self) -> uid_t {
+        self.record(MockCall::GetCurrentUid);
         self.uid
     }
 
     fn get_current_username(&self) -> Option<Arc<OsStr>> {
-        self.users.get(&self.uid).map(|u| Arc::clone(&u.name_arc))
+        self.record(MockCall::GetCurrentUsername);
+        self.lookup_user_by_uid(self.uid).ok()?.map(|u| Arc::clone(&u.name_arc))
     }
 
     fn get_effective_uid(&self) -> uid_t {
-        self.uid
+        self.record(MockCall::GetEffectiveUid);
+        self.euid
     }
 
     fn get_effective_username(&self) -> Option<Arc<OsStr>> {
-        self.users.get(&self.uid).map(|u| Arc::clone(&u.name_arc))
+        self.record(MockCall::GetEffectiveUsername);
+        self.lookup_user_by_uid(self.euid).ok()?.map(|u| Arc::clone(&u.name_arc))
     }
 }
 
 
 impl Groups for MockUsers {
     fn get_group_by_gid(&self, gid: gid_t) -> Option<Arc<Group>> {
-        self.groups.get(&gid).cloned()
+        self.record(MockCall::GetGroupByGid(gid));
+        self.lookup_group_by_gid(gid).ok()?
     }
 
     fn get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Option<Arc<Group>> {
-        self.groups.values().find(|g| g.name() == group_name.as_ref()).cloned()
+        self.record(MockCall::GetGroupByName(group_name.into()));
+        self.lookup_group_by_name(group_name.as_ref()).ok()?
     }
 
-    fn get_current_gid(&self) -> uid_t {
-        self.uid
+    fn try_get_group_by_gid(&self, gid: gid_t) -> Result<Option<Arc<Group>>, LookupError> {
+        self.record(MockCall::TryGetGroupByGid(gid));
+        self.lookup_group_by_gid(gid)
+    }
+
+    fn try_get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Result<Option<Arc<Group>>, LookupError> {
+        self.record(MockCall::TryGetGroupByName(group_name.into()));
+        self.lookup_group_by_name(group_name.as_ref())
+    }
+
+    fn get_current_gid(&self) -> gid_t {
+        self.record(MockCall::GetCurrentGid);
+        self.gid
     }
 
     fn get_current_groupname(&self) -> Option<Arc<OsStr>> {
-        self.groups.get(&self.uid).map(|u| Arc::clone(&u.name_arc))
+        self.record(MockCall::GetCurrentGroupname);
+        self.lookup_group_by_gid(self.gid).ok()?.map(|g| Arc::clone(&g.name_arc))
     }
 
-    fn get_effective_gid(&self) -> uid_t {
-        self.uid
+    fn get_effective_gid(&self) -> gid_t {
+        self.record(MockCall::GetEffectiveGid);
+        self.egid
     }
 
     fn get_effective_groupname(&self) -> Option<Arc<OsStr>> {
-        self.groups.get(&self.uid).map(|u| Arc::clone(&u.name_arc))
+        self.record(MockCall::GetEffectiveGroupname);
+        self.lookup_group_by_gid(self.egid).ok()?.map(|g| Arc::clone(&g.name_arc))
+    }
+
+    /// Returns the group with the given ID, if it’s in the table, followed
+    /// by every group that lists the user as a member, in order of ID.
+    fn get_user_groups<S: AsRef<OsStr> + ?Sized>(&self, username: &S, gid: gid_t) -> Option<Vec<Arc<Group>>> {
+        let username = username.as_ref();
+        self.record(MockCall::GetUserGroups(username.into(), gid));
+        self.script(MockLookup::Memberships(username.into())).ok()?;
+
+        let mut others = self.groups.values()
+                             .filter(|g| g.gid() != gid && g.members().iter().any(|m| m == username))
+                             .cloned()
+                             .collect::<Vec<_>>();
+        others.sort_by_key(|g| g.gid());
+
+        Some(self.groups.get(&gid).cloned().into_iter().chain(others).collect())
+    }
+
+    fn is_member(&self, user: &User, gid: gid_t) -> bool {
+        self.record(MockCall::IsMember(user.name().into(), gid));
+
+        if user.primary_group_id() == gid {
+            return true;
+        }
+
+        if self.script(MockLookup::Memberships(user.name().into())).is_err() {
+            return false;
+        }
+
+        match self.groups.get(&gid) {
+            Some(group) => group.members().iter().any(|m| m == user.name()),
+            None        => false,
+        }
     }
 }
 
 
 #[cfg(test)]
 mod test {
-    use super::MockUsers;
-    use base::{User, Group};
+    use super::{MockUsers, MockLookup, MockCall};
+    use base::{User, Group, LookupError};
     use traits::{Users, Groups};
 
-    use std::ffi::OsStr;
+    use base::os::unix::{GroupExt, UserExt};
+
+    use std::ffi::{OsStr, OsString};
     use std::sync::Arc;
+    use std::time::{Duration, Instant};
 
     #[test]
     fn current_username() {
@@ -234,4 +668,154 @@ mod test {
         assert_eq!(None,
                    users.get_group_by_gid(1337).map(|g| Arc::clone(&g.name_arc)))
     }
+
+    #[test]
+    fn separate_ids() {
+        let users = MockUsers::with_current_uid(1000)
+            .with_effective_uid(0)
+            .with_current_gid(100)
+            .with_effective_gid(50)
+            .with_user(User::new(1000, "fred", 100))
+            .with_user(User::new(0, "root", 0))
+            .with_group(Group::new(100, "users"))
+            .with_group(Group::new(50, "staff"));
+
+        assert_eq!(users.get_current_uid(), 1000);
+        assert_eq!(users.get_effective_username(), Some(Arc::from(OsStr::new("root"))));
+        assert_eq!(users.get_current_groupname(), Some(Arc::from(OsStr::new("users"))));
+        assert_eq!(users.get_effective_groupname(), Some(Arc::from(OsStr::new("staff"))));
+    }
+
+    #[test]
+    fn same_ids_by_default() {
+        let users = MockUsers::with_current_uid(1337);
+        assert_eq!(users.get_effective_uid(), 1337);
+        assert_eq!(users.get_current_gid(), 1337);
+        assert_eq!(users.get_effective_gid(), 1337);
+    }
+
+    #[test]
+    fn memberships() {
+        let mut users = MockUsers::with_current_uid(0)
+            .with_user(User::new(1000, "fred", 100))
+            .with_group(Group::new(100, "users"))
+            .with_group(Group::new(27, "sudo"))
+            .with_group(Group::new(20, "dialout"))
+            .with_member("fred", 27)
+            .with_member("fred", 20);
+
+        assert!(users.add_member("fred", 27));
+        assert!(!users.add_member("fred", 999));
+
+        let gids = users.get_user_groups("fred", 100).unwrap().iter().map(|g| g.gid()).collect::<Vec<_>>();
+        assert_eq!(gids, vec![100, 20, 27]);
+        assert_eq!(users.get_group_by_gid(27).unwrap().members(), &[OsString::from("fred")]);
+
+        let fred = User::new(1000, "fred", 100);
+        assert!(users.is_member(&fred, 100));
+        assert!(users.is_member(&fred, 20));
+        assert!(!users.is_member(&fred, 999));
+        assert!(!users.is_member(&User::new(1001, "nobody", 65534), 27));
+        assert_eq!(users.get_user_groups("nobody", 65534).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn from_passwd_str() {
+        let passwd = "# comment\nroot:x:0:0:root:/root:/bin/sh\nfred:x:1000:100:Fred:/home/fred:/bin/zsh\ntoor:x:0:0::/root:/bin/sh\n";
+        let group = "root:x:0:\nusers:x:100:\nsudo:x:27:fred,jim\n";
+
+        let users = MockUsers::from_passwd_str(passwd, group).unwrap().with_current_user(1000);
+        assert_eq!(users.get_current_gid(), 100);
+        assert_eq!(users.get_effective_groupname(), Some(Arc::from(OsStr::new("users"))));
+        assert_eq!(users.get_user_by_uid(0).unwrap().name(), "root");
+        assert_eq!(users.get_user_by_name("fred").unwrap().shell(), ::std::path::Path::new("/bin/zsh"));
+
+        let gids = users.get_user_groups("fred", 100).unwrap().iter().map(|g| g.gid()).collect::<Vec<_>>();
+        assert_eq!(gids, vec![100, 27]);
+    }
+
+    #[test]
+    fn from_passwd_str_error() {
+        let error = MockUsers::from_passwd_str("fred:x:1000\n", "").err().unwrap();
+        assert_eq!(error.line(), 1);
+    }
+
+    #[test]
+    fn failures() {
+        let mut users = MockUsers::with_current_uid(1000)
+            .with_user(User::new(1000, "fred", 100))
+            .with_group(Group::new(100, "users"));
+        let error = LookupError::new("getpwnam_r", libc::EIO);
+
+        users.fail_lookup(MockLookup::Username("fred".into()), error);
+        users.fail_lookup(MockLookup::Gid(100), error);
+        assert_eq!(users.try_get_user_by_name("fred").err(), Some(error));
+        assert!(users.get_user_by_name("fred").is_none());
+        assert!(users.get_current_groupname().is_none());
+        assert!(users.get_user_by_uid(1000).is_some());
+
+        users.clear_failures();
+        assert!(users.get_user_by_name("fred").is_some());
+    }
+
+    #[test]
+    fn membership_failures() {
+        let mut users = MockUsers::with_current_uid(0)
+            .with_group(Group::new(100, "users"))
+            .with_group(Group::new(27, "sudo"))
+            .with_member("fred", 27);
+        let fred = User::new(1000, "fred", 100);
+
+        users.fail_lookup_times(MockLookup::Memberships("fred".into()), LookupError::new("getgrouplist", libc::EIO), 2);
+        assert!(users.get_user_groups("fred", 100).is_none());
+        assert!(!users.is_member(&fred, 27));
+        assert!(users.is_member(&fred, 27));
+        assert!(users.get_user_groups("fred", 100).is_some());
+
+        // The primary group is known without looking anything up.
+        users.fail_lookup(MockLookup::Memberships("fred".into()), LookupError::new("getgrouplist", libc::EIO));
+        assert!(users.is_member(&fred, 100));
+    }
+
+    #[test]
+    fn flaky_failures() {
+        let mut users = MockUsers::with_current_uid(0).with_group(Group::new(100, "users"));
+        users.fail_lookup_times(MockLookup::GroupName("users".into()), LookupError::new("getgrnam_r", libc::EAGAIN), 2);
+
+        assert!(users.try_get_group_by_name("users").is_err());
+        assert!(users.try_get_group_by_name("users").is_err());
+        assert!(users.try_get_group_by_name("users").unwrap().is_some());
+        assert!(users.try_get_group_by_name("users").unwrap().is_some());
+    }
+
+    #[test]
+    fn delays() {
+        let mut users = MockUsers::with_current_uid(0);
+        users.delay_lookup(MockLookup::Gid(5), Duration::from_millis(20));
+
+        let start = Instant::now();
+        assert!(users.get_group_by_gid(5).is_none());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn calls() {
+        let users = MockUsers::with_current_uid(1000).with_user(User::new(1000, "fred", 100));
+        users.get_current_uid();
+        users.get_user_by_name("fred");
+        users.get_effective_groupname();
+        users.get_user_groups("fred", 100);
+        assert!(users.is_member(&User::new(1000, "fred", 100), 100));
+
+        assert_eq!(users.calls(), vec![
+            MockCall::GetCurrentUid,
+            MockCall::GetUserByName("fred".into()),
+            MockCall::GetEffectiveGroupname,
+            MockCall::GetUserGroups("fred".into(), 100),
+            MockCall::IsMember("fred".into(), 100),
+        ]);
+
+        users.clear_calls();
+        assert_eq!(users.calls(), vec![]);
+    }
 }
@@ -0,0 +1,418 @@
+//! Checking passwords against `crypt(3)` hashes.
+//!
+//! The password field of a user or a shadow entry holds a hash in one of
+//! the formats understood by the C library’s `crypt` function, which start
+//! with an ID between dollar signs. This module checks a candidate password
+//! against such a hash, by hashing it with the same settings and comparing
+//! the results.
+//!
+//! Only the formats in [`HashFormat`](enum.HashFormat.html) are accepted.
+//! Hashes in any other format, including the old DES format, are rejected
+//! rather than passed to `crypt`, as are hashes that have been locked or
+//! left empty.
+//!
+//! Whether a format actually works depends on the platform’s `crypt`: the
+//! libxcrypt used by most Linux distributions supports all of them, while
+//! others support fewer. A format that `crypt` doesn’t support is reported
+//! as an error instead of as a wrong password.
+//!
+//! This module is only present with the `password` feature, as it links
+//! against `libcrypt`. On Linux, it uses the reentrant `crypt_r`, so any
+//! number of threads can check passwords at once. Elsewhere, it uses
+//! `crypt`, and only one thread at a time can be checking a password.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use users::get_user_by_name;
+//! use users::password::verify_user_password;
+//!
+//! let user = get_user_by_name("fred").unwrap();
+//! match verify_user_password(&user, "hunter2") {
+//!     Ok(true)  => println!("Welcome back!"),
+//!     Ok(false) => println!("Wrong password"),
+//!     Err(e)    => println!("Can’t check the password: {}", e),
+//! }
+//! ```
+
+use std::error::Error;
+use std::ffi::{CStr, CString, OsStr};
+use std::fmt;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+
+use libc::c_char;
+
+#[cfg(target_os = "linux")]
+use libc::c_void;
+
+use base::{User, LookupError};
+#[cfg(not(target_os = "linux"))]
+use base::StaticLock;
+use base::os::unix::UserExt;
+use shadow::is_locked_hash;
+
+#[cfg(target_os = "linux")]
+use shadow::try_get_shadow_by_name;
+
+
+#[cfg_attr(any(target_os = "linux", target_os = "freebsd", target_os = "dragonfly", target_os = "netbsd"), link(name = "crypt"))]
+extern "C" {
+    #[cfg(target_os = "linux")]
+    fn crypt_r(key: *const c_char, salt: *const c_char, data: *mut c_void) -> *mut c_char;
+
+    #[cfg(not(target_os = "linux"))]
+    fn crypt(key: *const c_char, salt: *const c_char) -> *mut c_char;
+}
+
+/// The size of the `struct crypt_data` that `crypt_r` works in. This is the
+/// size of the one in glibc’s old libcrypt, which is the biggest of them:
+/// libxcrypt’s is 32 KiB, and musl’s is smaller still. It’s a multiple of
+/// eight bytes.
+#[cfg(target_os = "linux")]
+const CRYPT_DATA_SIZE: usize = 131_232;
+
+/// `crypt` returns a pointer to a static buffer, so only one thread can
+/// use it at a time.
+#[cfg(not(target_os = "linux"))]
+static CRYPT_LOCK: StaticLock = StaticLock::new();
+
+
+/// The password hash formats that can be verified.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HashFormat {
+
+    /// MD5-based hashes, starting with `$1$`.
+    Md5,
+
+    /// SHA-256-based hashes, starting with `$5$`.
+    Sha256,
+
+    /// SHA-512-based hashes, starting with `$6$`.
+    Sha512,
+
+    /// bcrypt hashes, starting with `$2b$`, or the older `$2a$` and `$2y$`.
+    Bcrypt,
+
+    /// yescrypt hashes, starting with `$y$`.
+    Yescrypt,
+}
+
+impl HashFormat {
+
+    /// Works out which format a hash is in from its prefix, returning
+    /// `None` if it’s not one of the supported formats.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::password::HashFormat;
+    ///
+    /// assert_eq!(HashFormat::detect("$6$salt$hash"), Some(HashFormat::Sha512));
+    /// assert_eq!(HashFormat::detect("!$6$salt$hash"), None);
+    /// ```
+    pub fn detect<S: AsRef<OsStr> + ?Sized>(hash: &S) -> Option<Self> {
+        let hash = hash.as_ref().as_bytes();
+
+        if hash.starts_with(b"$1$")        { Some(HashFormat::Md5) }
+        else if hash.starts_with(b"$5$")   { Some(HashFormat::Sha256) }
+        else if hash.starts_with(b"$6$")   { Some(HashFormat::Sha512) }
+        else if hash.starts_with(b"$2b$")
+             || hash.starts_with(b"$2a$")
+             || hash.starts_with(b"$2y$")  { Some(HashFormat::Bcrypt) }
+        else if hash.starts_with(b"$y$")   { Some(HashFormat::Yescrypt) }
+        else                               { None }
+    }
+}
+
+
+/// Checks whether a candidate password matches the given hash.
+///
+/// The hash can come from [`UserExt::password`](../os/unix/trait.UserExt.html#tymethod.password)
+/// or [`ShadowEntry::password`](../shadow/struct.ShadowEntry.html#method.password).
+/// The results are compared in constant time.
+///
+/// # Errors
+///
+/// This function will return `Err` instead of `Ok(false)` if the hash is
+/// locked, empty, or in a format that can’t be checked, so that callers can
+/// decide for themselves how to treat those accounts.
+///
+/// # Examples
+///
+/// ```
+/// use users::password::{verify_password, VerifyError};
+///
+/// let hash = "$6$saltsalt$qFmFH.bQmmtXzyBY0s9v7Oicd2z4XSIecDzlB5KiA2/jctKu9YterLp8wwnSq.qc.eoxqOmSuNp2xS0ktL3nh/";
+/// assert_eq!(verify_password(hash, "password").unwrap(), true);
+/// assert_eq!(verify_password(hash, "hunter2").unwrap(), false);
+///
+/// let locked = format!("!{}", hash);
+/// match verify_password(&locked, "password") {
+///     Err(VerifyError::Locked) => println!("That account is locked"),
+///     _                        => unreachable!(),
+/// }
+/// ```
+pub fn verify_password<H, C>(hash: &H, candidate: &C) -> Result<bool, VerifyError>
+where H: AsRef<OsStr> + ?Sized,
+      C: AsRef<[u8]> + ?Sized,
+{
+    let hash = hash.as_ref().as_bytes();
+
+    if hash.is_empty() {
+        return Err(VerifyError::Empty);
+    }
+
+    if is_locked_hash(hash) {
+        return Err(VerifyError::Locked);
+    }
+
+    let format = HashFormat::detect(OsStr::from_bytes(hash)).ok_or(VerifyError::UnsupportedFormat)?;
+
+    let candidate = match CString::new(candidate.as_ref()) {
+        Ok(c)  => c,
+        Err(_) => {
+            // A password with a null character in it could never have
+            // been hashed, so it can’t match.
+            return Ok(false);
+        }
+    };
+
+    let setting = CString::new(hash).map_err(|_| VerifyError::UnsupportedFormat)?;
+    let hashed = crypt_hash(&candidate, &setting)?;
+
+    if ! hashed.starts_with(&hash[.. prefix_len(format)]) {
+        // Some crypt implementations fall back to DES when they don’t
+        // recognise the setting, rather than failing.
+        return Err(VerifyError::UnsupportedFormat);
+    }
+
+    Ok(constant_time_eq(&hashed, hash))
+}
+
+/// Checks whether a candidate password is the given user’s password.
+///
+/// If the user’s password field is the `x` placeholder, the hash is looked
+/// up in the shadow database instead, on platforms that have one.
+///
+/// # Errors
+///
+/// This function will return `Err` for the same reasons as
+/// [`verify_password`](fn.verify_password.html), or if the shadow database
+/// can’t be read, or has no entry for the user.
+pub fn verify_user_password<C: AsRef<[u8]> + ?Sized>(user: &User, candidate: &C) -> Result<bool, VerifyError> {
+    #[cfg(target_os = "linux")]
+    {
+        if user.password() == "x" {
+            return match try_get_shadow_by_name(user.name()) {
+                Ok(Some(entry))  => verify_password(entry.password(), candidate),
+                Ok(None)         => Err(VerifyError::NoShadowEntry),
+                Err(e)           => Err(VerifyError::Lookup(e)),
+            };
+        }
+    }
+
+    verify_password(user.password(), candidate)
+}
+
+
+/// An error that stops a password from being checked.
+#[derive(Debug)]
+pub enum VerifyError {
+
+    /// The hash has been locked, so no password will ever match it.
+    Locked,
+
+    /// The hash is empty, which means that no password is needed. Whether
+    /// that should be allowed is up to the caller.
+    Empty,
+
+    /// The hash is not in one of the supported formats, or the system’s
+    /// `crypt` does not support it.
+    UnsupportedFormat,
+
+    /// The user’s password is in the shadow database, but there is no entry
+    /// for them in it.
+    NoShadowEntry,
+
+    /// The shadow database could not be read.
+    Lookup(LookupError),
+
+    /// The system’s `crypt` function failed.
+    Crypt(io::Error),
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VerifyError::Locked             => write!(f, "password is locked"),
+            VerifyError::Empty              => write!(f, "password is empty"),
+            VerifyError::UnsupportedFormat  => write!(f, "unsupported password hash format"),
+            VerifyError::NoShadowEntry      => write!(f, "no shadow entry for user"),
+            VerifyError::Lookup(ref e)      => write!(f, "{}", e),
+            VerifyError::Crypt(ref e)       => write!(f, "crypt failed: {}", e),
+        }
+    }
+}
+
+impl Error for VerifyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            VerifyError::Lookup(ref e)  => Some(e),
+            VerifyError::Crypt(ref e)   => Some(e),
+            _                           => None,
+        }
+    }
+}
+
+
+/// Runs `crypt_r` with a zeroed `struct crypt_data` of its own, so no lock
+/// is needed. The buffer is made of `u64`s to give it the alignment of the
+/// `long` fields in glibc’s version.
+#[cfg(target_os = "linux")]
+fn crypt_hash(key: &CStr, setting: &CStr) -> Result<Vec<u8>, VerifyError> {
+    let mut data = vec![0_u64; CRYPT_DATA_SIZE / 8];
+
+    unsafe {
+        let result = crypt_r(key.as_ptr(), setting.as_ptr(), data.as_mut_ptr() as *mut c_void);
+        copy_hash(result)
+    }
+}
+
+/// Runs `crypt` while holding the lock, copying the result out of its
+/// static buffer before letting go.
+#[cfg(not(target_os = "linux"))]
+fn crypt_hash(key: &CStr, setting: &CStr) -> Result<Vec<u8>, VerifyError> {
+    let _lock = CRYPT_LOCK.lock();
+
+    unsafe {
+        let result = crypt(key.as_ptr(), setting.as_ptr());
+        copy_hash(result)
+    }
+}
+
+/// Checks the string returned by `crypt` or `crypt_r`, and copies the hash
+/// out of it. The string has to still be valid, so this has to happen
+/// before the lock or the buffer goes away.
+unsafe fn copy_hash(result: *const c_char) -> Result<Vec<u8>, VerifyError> {
+    if result.is_null() {
+        let error = io::Error::last_os_error();
+        return match error.raw_os_error() {
+            Some(libc::EINVAL) | Some(libc::ENOSYS) | Some(libc::EOPNOTSUPP) => Err(VerifyError::UnsupportedFormat),
+            _                                                               => Err(VerifyError::Crypt(error)),
+        };
+    }
+
+    let hashed = CStr::from_ptr(result).to_bytes().to_vec();
+
+    // libxcrypt returns a string starting with `*` instead of null when it
+    // can’t use the setting.
+    if hashed.starts_with(b"*") {
+        return Err(VerifyError::UnsupportedFormat);
+    }
+
+    Ok(hashed)
+}
+
+/// The length of the identifying prefix of a hash in the given format.
+fn prefix_len(format: HashFormat) -> usize {
+    match format {
+        HashFormat::Bcrypt  => 4,
+        _                   => 3,
+    }
+}
+
+/// Compares two byte strings without stopping at the first difference, so
+/// the time taken does not reveal how much of a hash was guessed right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    macro_rules! assert_err {
+        ($result:expr, $error:pat) => {
+            match $result {
+                Err($error) => {},
+                other       => panic!("expected {}, got {:?}", stringify!($error), other),
+            }
+        };
+    }
+
+    const MD5: &str = "$1$saltsalt$qjXMvbEw8oaL.CzflDtaK/";
+    const SHA256: &str = "$5$saltsalt$gOjOtoMpVhru2uyjeJSEc/JaLQWOXMNmlOnj6T4AtC.";
+    const SHA512: &str = "$6$saltsalt$qFmFH.bQmmtXzyBY0s9v7Oicd2z4XSIecDzlB5KiA2/jctKu9YterLp8wwnSq.qc.eoxqOmSuNp2xS0ktL3nh/";
+    const BCRYPT: &str = "$2b$05$saltsaltsaltsaltsaltsuapUmX2fgDiM636IzaIfRYs71yX8aW9i";
+    const YESCRYPT: &str = "$y$j9T$saltsaltsalt$WJhblAc/BKcuw1LHqgcyvlsjC8J4ha9Wl82.5/aQSy8";
+
+    #[test]
+    fn detect() {
+        assert_eq!(HashFormat::detect(MD5), Some(HashFormat::Md5));
+        assert_eq!(HashFormat::detect(SHA256), Some(HashFormat::Sha256));
+        assert_eq!(HashFormat::detect(SHA512), Some(HashFormat::Sha512));
+        assert_eq!(HashFormat::detect(BCRYPT), Some(HashFormat::Bcrypt));
+        assert_eq!(HashFormat::detect(YESCRYPT), Some(HashFormat::Yescrypt));
+        assert_eq!(HashFormat::detect("abJnggxhB/yWI"), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn verify_formats() {
+        for hash in &[MD5, SHA256, SHA512, BCRYPT, YESCRYPT] {
+            match verify_password(*hash, "password") {
+                Ok(matched) => {
+                    assert!(matched, "{} did not match", hash);
+                    assert!(!verify_password(*hash, "Password").unwrap());
+                }
+                Err(VerifyError::UnsupportedFormat) => {
+                    // This system’s crypt is too old for this format.
+                }
+                Err(e) => panic!("{}: {}", hash, e),
+            }
+        }
+    }
+
+    #[test]
+    fn locked() {
+        assert_err!(verify_password(&format!("!{}", SHA512), "password"), VerifyError::Locked);
+        assert_err!(verify_password("!!", "password"), VerifyError::Locked);
+        assert_err!(verify_password("*", ""), VerifyError::Locked);
+    }
+
+    #[test]
+    fn empty() {
+        assert_err!(verify_password("", ""), VerifyError::Empty);
+    }
+
+    #[test]
+    fn unsupported() {
+        assert_err!(verify_password("abJnggxhB/yWI", "password"), VerifyError::UnsupportedFormat);
+        assert_err!(verify_password("x", "password"), VerifyError::UnsupportedFormat);
+    }
+
+    #[test]
+    fn null_in_candidate() {
+        assert!(!verify_password(SHA512, "pass\0word").unwrap());
+    }
+
+    #[test]
+    fn user_password() {
+        let user = User::new(1000, "fred", 1000).with_password(SHA512);
+        assert!(verify_user_password(&user, "password").unwrap());
+        assert!(!verify_user_password(&user, "wrong").unwrap());
+    }
+
+    #[test]
+    fn comparison() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+    }
+}
@@ -0,0 +1,412 @@
+//! The shadow password database.
+//!
+//! On most systems, the password field of a user’s entry in `/etc/passwd`
+//! is just `x`: the real password hash, along with the password aging
+//! information, lives in `/etc/shadow`, which only root can read. This
+//! module reads that database, either through the C library with
+//! `getspnam_r` (on Linux), or by parsing a file in the `shadow(5)` format.
+//!
+//! All the dates and periods in a [`ShadowEntry`](struct.ShadowEntry.html)
+//! are counted in *days*, with dates counted from the Unix epoch, exactly as
+//! they appear in the file. Empty fields are returned as `None`.
+//!
+//! ## Example
+//!
+//! ```
+//! use users::shadow::ShadowFile;
+//!
+//! let shadow = ShadowFile::parse("fred:$6$salt$hash:19000:0:99999:7:::\n").unwrap();
+//! let fred = &shadow.entries()[0];
+//! assert_eq!(fred.last_change(), Some(19000));
+//! assert_eq!(fred.max_age(), Some(99999));
+//! assert_eq!(fred.inactive_period(), None);
+//! ```
+
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+#[cfg(target_os = "linux")]
+use std::mem;
+#[cfg(target_os = "linux")]
+use std::ptr;
+
+#[cfg(all(target_os = "linux", feature = "logging"))]
+extern crate log;
+#[cfg(all(target_os = "linux", feature = "logging"))]
+use self::log::trace;
+
+#[cfg(target_os = "linux")]
+use libc::spwd as c_spwd;
+
+#[cfg(target_os = "linux")]
+use base::{LookupError, check_lookup_result, grow_buffer, from_raw_buf};
+use files::{ParseError, ParseErrorKind, split_lines, is_other_line, split_fields, non_empty_name, bytes_to_os};
+
+
+/// One user’s entry in the shadow password database.
+///
+/// For more information, see the [module documentation](index.html).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShadowEntry {
+    name: OsString,
+    password: OsString,
+    last_change: Option<i64>,
+    min_age: Option<i64>,
+    max_age: Option<i64>,
+    warning_period: Option<i64>,
+    inactive_period: Option<i64>,
+    expire_date: Option<i64>,
+}
+
+impl ShadowEntry {
+
+    /// Create a new entry with the given username and password hash, and
+    /// all the password aging fields empty.
+    pub fn new<N: AsRef<OsStr> + ?Sized, P: AsRef<OsStr> + ?Sized>(name: &N, password: &P) -> Self {
+        Self {
+            name: name.as_ref().to_os_string(),
+            password: password.as_ref().to_os_string(),
+            last_change: None,
+            min_age: None,
+            max_age: None,
+            warning_period: None,
+            inactive_period: None,
+            expire_date: None,
+        }
+    }
+
+    /// Returns the username this entry is for.
+    pub fn name(&self) -> &OsStr {
+        &self.name
+    }
+
+    /// Returns the encrypted password. This may also be a locked password
+    /// starting with `!` or `*`, or empty if no password is needed.
+    pub fn password(&self) -> &OsStr {
+        &self.password
+    }
+
+    /// Returns whether the password has been locked, which is when it
+    /// starts with `!` or `*`, such as after `passwd -l`. An account with a
+    /// locked password can’t be logged into with any password.
+    pub fn is_locked(&self) -> bool {
+        is_locked_hash(self.password.as_bytes())
+    }
+
+    /// Returns the date the password was last changed. A value of zero
+    /// means the user has to change their password the next time they log
+    /// in.
+    pub fn last_change(&self) -> Option<i64> {
+        self.last_change
+    }
+
+    /// Returns the number of days the user has to wait after changing their
+    /// password before they can change it again.
+    pub fn min_age(&self) -> Option<i64> {
+        self.min_age
+    }
+
+    /// Returns the number of days after which the user has to change their
+    /// password.
+    pub fn max_age(&self) -> Option<i64> {
+        self.max_age
+    }
+
+    /// Returns the number of days before the password expires during which
+    /// the user gets warned about it.
+    pub fn warning_period(&self) -> Option<i64> {
+        self.warning_period
+    }
+
+    /// Returns the number of days after the password expires during which
+    /// the user can still log in, as long as they change it.
+    pub fn inactive_period(&self) -> Option<i64> {
+        self.inactive_period
+    }
+
+    /// Returns the date on which the account expires.
+    pub fn expire_date(&self) -> Option<i64> {
+        self.expire_date
+    }
+
+    /// Sets the date the password was last changed.
+    pub fn with_last_change(mut self, days: Option<i64>) -> Self {
+        self.last_change = days;
+        self
+    }
+
+    /// Sets the minimum password age.
+    pub fn with_min_age(mut self, days: Option<i64>) -> Self {
+        self.min_age = days;
+        self
+    }
+
+    /// Sets the maximum password age.
+    pub fn with_max_age(mut self, days: Option<i64>) -> Self {
+        self.max_age = days;
+        self
+    }
+
+    /// Sets the password warning period.
+    pub fn with_warning_period(mut self, days: Option<i64>) -> Self {
+        self.warning_period = days;
+        self
+    }
+
+    /// Sets the password inactivity period.
+    pub fn with_inactive_period(mut self, days: Option<i64>) -> Self {
+        self.inactive_period = days;
+        self
+    }
+
+    /// Sets the account expiration date.
+    pub fn with_expire_date(mut self, days: Option<i64>) -> Self {
+        self.expire_date = days;
+        self
+    }
+}
+
+
+/// The contents of a file in the `shadow(5)` format.
+#[derive(Clone, Debug, Default)]
+pub struct ShadowFile {
+    entries: Vec<ShadowEntry>,
+}
+
+impl ShadowFile {
+
+    /// Parses the contents of a `shadow(5)` file. Comments, blank lines,
+    /// and NIS compatibility lines are skipped.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` if any other line does not have nine
+    /// fields, or has a numeric field that isn’t a number.
+    pub fn parse<S: AsRef<[u8]> + ?Sized>(input: &S) -> Result<Self, ParseError> {
+        let mut entries = Vec::new();
+
+        for (number, line, _) in split_lines(input.as_ref()) {
+            if is_other_line(line) {
+                continue;
+            }
+
+            let fields = split_fields(line, 9, number)?;
+            let name = non_empty_name(fields[0], number)?;
+            let days = |field| parse_days(field, number);
+
+            let entry = ShadowEntry::new(name, OsStr::from_bytes(fields[1]))
+                .with_last_change(days(fields[2])?)
+                .with_min_age(days(fields[3])?)
+                .with_max_age(days(fields[4])?)
+                .with_warning_period(days(fields[5])?)
+                .with_inactive_period(days(fields[6])?)
+                .with_expire_date(days(fields[7])?);
+
+            entries.push(entry);
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Reads and parses the `shadow(5)` file at the given path.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` when the file can’t be read, or with
+    /// an error of kind `InvalidData` wrapping a
+    /// [`ParseError`](../files/struct.ParseError.html) when it can’t be
+    /// parsed.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let contents = fs::read(path)?;
+        Self::parse(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Returns the entries in the file, in order.
+    pub fn entries(&self) -> &[ShadowEntry] {
+        &self.entries
+    }
+
+    /// Returns the first entry for the given username, if there is one.
+    pub fn get<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Option<&ShadowEntry> {
+        self.entries.iter().find(|e| e.name == username.as_ref())
+    }
+}
+
+/// Whether a password field has been locked, or never had a password set,
+/// and so can’t match any password.
+pub(crate) fn is_locked_hash(hash: &[u8]) -> bool {
+    hash.starts_with(b"!") || hash.starts_with(b"*")
+}
+
+/// Parses one of the numeric fields, which may be empty, or `-1` to mean
+/// the same as empty. Any other negative number is an error.
+fn parse_days(field: &[u8], number: usize) -> Result<Option<i64>, ParseError> {
+    if field.is_empty() {
+        return Ok(None);
+    }
+
+    let days = std::str::from_utf8(field).ok()
+                   .filter(|s| s.bytes().enumerate().all(|(i, b)| b.is_ascii_digit() || (i == 0 && b == b'-')))
+                   .and_then(|s| s.parse::<i64>().ok());
+
+    match days {
+        Some(-1)                 => Ok(None),
+        Some(days) if days >= 0  => Ok(Some(days)),
+        _                        => Err(ParseError::new(number, ParseErrorKind::InvalidNumber(bytes_to_os(field)))),
+    }
+}
+
+
+/// Searches for the shadow entry of the user with the given name, using the
+/// C library’s `getspnam_r`. Returns it if one is found, otherwise returns
+/// `None`.
+///
+/// Reading the shadow database usually needs root privileges. Use
+/// [`try_get_shadow_by_name`](fn.try_get_shadow_by_name.html) to tell a
+/// missing entry apart from a permission error.
+#[cfg(target_os = "linux")]
+pub fn get_shadow_by_name<S: AsRef<OsStr> + ?Sized>(username: &S) -> Option<ShadowEntry> {
+    try_get_shadow_by_name(username).unwrap_or(None)
+}
+
+/// Searches for the shadow entry of the user with the given name, using the
+/// C library’s `getspnam_r`.
+///
+/// # Errors
+///
+/// This function will return `Err` if the lookup itself failed, such as
+/// with `EACCES` when the process is not allowed to read the database.
+#[cfg(target_os = "linux")]
+pub fn try_get_shadow_by_name<S: AsRef<OsStr> + ?Sized>(username: &S) -> Result<Option<ShadowEntry>, LookupError> {
+    let username = match CString::new(username.as_ref().as_bytes()) {
+        Ok(u)  => u,
+        Err(_) => {
+            // The username that was passed in contained a null character,
+            // which will match no usernames.
+            return Ok(None);
+        }
+    };
+
+    let mut spwd = unsafe { mem::zeroed::<c_spwd>() };
+    let mut buf = vec![0; 2048];
+    let mut result = ptr::null_mut::<c_spwd>();
+
+    #[cfg(feature = "logging")]
+    trace!("Running getspnam_r for user {:?}", username.as_ref());
+
+    loop {
+        let r = unsafe {
+            libc::getspnam_r(username.as_ptr(), &mut spwd, buf.as_mut_ptr(), buf.len(), &mut result)
+        };
+
+        if r != libc::ERANGE {
+            check_lookup_result("getspnam_r", r)?;
+            break;
+        }
+
+        grow_buffer("getspnam_r", &mut buf)?;
+    }
+
+    if result.is_null() {
+        // There is no such user.
+        return Ok(None);
+    }
+
+    if result != &mut spwd {
+        // The result of getspnam_r should be its input spwd.
+        return Ok(None);
+    }
+
+    let entry = unsafe { spwd_to_entry(result.read()) };
+    Ok(Some(entry))
+}
+
+/// Reads data from the `c_spwd` and returns it as a `ShadowEntry`.
+#[cfg(target_os = "linux")]
+unsafe fn spwd_to_entry(spwd: c_spwd) -> ShadowEntry {
+    // c_long is only 64 bits wide on some platforms.
+    #[allow(clippy::useless_conversion)]
+    let days = |value: libc::c_long| if value < 0 { None } else { Some(i64::from(value)) };
+
+    ShadowEntry {
+        name:            from_raw_buf::<OsString>(spwd.sp_namp),
+        password:        from_raw_buf::<OsString>(spwd.sp_pwdp),
+        last_change:     days(spwd.sp_lstchg),
+        min_age:         days(spwd.sp_min),
+        max_age:         days(spwd.sp_max),
+        warning_period:  days(spwd.sp_warn),
+        inactive_period: days(spwd.sp_inact),
+        expire_date:     days(spwd.sp_expire),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SHADOW: &str = "\
+root:!:19000:0:99999:7:::
+# comment
+daemon:*:18000::::::
+fred:$6$salt$hash:19500:1:90:14:30:20000:
+";
+
+    #[test]
+    fn parse_fields() {
+        let shadow = ShadowFile::parse(SHADOW).unwrap();
+        assert_eq!(shadow.entries().len(), 3);
+
+        let fred = shadow.get("fred").unwrap();
+        assert_eq!(fred.password(), "$6$salt$hash");
+        assert_eq!(fred.last_change(), Some(19500));
+        assert_eq!(fred.min_age(), Some(1));
+        assert_eq!(fred.max_age(), Some(90));
+        assert_eq!(fred.warning_period(), Some(14));
+        assert_eq!(fred.inactive_period(), Some(30));
+        assert_eq!(fred.expire_date(), Some(20000));
+    }
+
+    #[test]
+    fn parse_empty_fields() {
+        let shadow = ShadowFile::parse(SHADOW).unwrap();
+        assert_eq!(shadow.get("daemon"), Some(&ShadowEntry::new("daemon", "*").with_last_change(Some(18000))));
+    }
+
+    #[test]
+    fn parse_invalid_number() {
+        let error = ShadowFile::parse("root:!:19000:0:99999:7:::\nfred:x:soon::::::\n").unwrap_err();
+        assert_eq!(error.line(), 2);
+        assert_eq!(error.kind(), &ParseErrorKind::InvalidNumber(OsString::from("soon")));
+    }
+
+    #[test]
+    fn parse_negative_numbers() {
+        let shadow = ShadowFile::parse("fred:x:-1:0:-1::::\n").unwrap();
+        assert_eq!(shadow.get("fred").unwrap().last_change(), None);
+        assert_eq!(shadow.get("fred").unwrap().max_age(), None);
+
+        let error = ShadowFile::parse("fred:x:19000:0:-2::::\n").unwrap_err();
+        assert_eq!(error.kind(), &ParseErrorKind::InvalidNumber(OsString::from("-2")));
+    }
+
+    #[test]
+    fn parse_field_count() {
+        let error = ShadowFile::parse("root:!:19000\n").unwrap_err();
+        assert_eq!(error.kind(), &ParseErrorKind::FieldCount { expected: 9, found: 3 });
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn lookup_missing_user() {
+        match try_get_shadow_by_name("this user does not exist") {
+            Ok(entry) => assert_eq!(entry, None),
+            Err(e)    => assert_eq!(e.raw_os_error(), libc::EACCES),
+        }
+    }
+}
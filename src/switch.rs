@@ -1,14 +1,30 @@
 //! Functions for switching the running process’s user or group.
 
 use std::io;
+use std::process;
 use libc::{uid_t, gid_t, c_int};
 
-use base::{get_effective_uid, get_effective_gid};
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub mod fs;
+
+#[cfg(target_os = "linux")]
+pub mod thread;
+
+#[cfg(feature = "logging")]
+extern crate log;
+#[cfg(feature = "logging")]
+use self::log::error;
+
+use base::{User, get_effective_uid, get_effective_gid, user_group_ids};
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd", target_os = "dragonfly", target_os = "openbsd"))]
+use base::{get_resuid, get_resgid};
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd", target_os = "dragonfly", target_os = "openbsd")))]
+use base::{get_current_uid, get_current_gid};
 
 
 // NOTE: for whatever reason, it seems these are not available in libc on BSD platforms, so they
 //       need to be included manually
-extern {
+extern "C" {
     fn setreuid(ruid: uid_t, euid: uid_t) -> c_int;
     fn setregid(rgid: gid_t, egid: gid_t) -> c_int;
 }
@@ -200,19 +216,230 @@ pub fn set_both_gid(rgid: gid_t, egid: gid_t) -> io::Result<()> {
     }
 }
 
+/// Sets the **current user**, the **effective user**, and the **saved
+/// set-user-ID** for the running process to the ones with the given user
+/// IDs.
+///
+/// Setting all three to the same unprivileged ID is the only way to make
+/// sure a process can’t switch back to root later.
+///
+/// Typically, trying to switch to anyone other than the user already running
+/// the process requires root privileges.
+///
+/// # libc functions used
+///
+/// - [`setresuid`](https://docs.rs/libc/*/libc/fn.setresuid.html)
+///
+/// # Errors
+///
+/// This function will return `Err` when an I/O error occurs during the
+/// `setresuid` call.
+///
+/// # Examples
+///
+/// ```no_run
+/// use users::switch::set_res_uid;
+///
+/// set_res_uid(1001, 1001, 1001);
+/// // current, effective, and saved user IDs are 1001
+/// ```
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd", target_os = "dragonfly", target_os = "openbsd"))]
+pub fn set_res_uid(ruid: uid_t, euid: uid_t, suid: uid_t) -> io::Result<()> {
+    match unsafe { libc::setresuid(ruid, euid, suid) } {
+         0 => Ok(()),
+        -1 => Err(io::Error::last_os_error()),
+         n => unreachable!("setresuid returned {}", n)
+    }
+}
+
+/// Sets the **current group**, the **effective group**, and the **saved
+/// set-group-ID** for the running process to the ones with the given group
+/// IDs.
+///
+/// Typically, trying to switch to any group other than the group already
+/// running the process requires root privileges.
+///
+/// # libc functions used
+///
+/// - [`setresgid`](https://docs.rs/libc/*/libc/fn.setresgid.html)
+///
+/// # Errors
+///
+/// This function will return `Err` when an I/O error occurs during the
+/// `setresgid` call.
+///
+/// # Examples
+///
+/// ```no_run
+/// use users::switch::set_res_gid;
+///
+/// set_res_gid(1001, 1001, 1001);
+/// // current, effective, and saved group IDs are 1001
+/// ```
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd", target_os = "dragonfly", target_os = "openbsd"))]
+pub fn set_res_gid(rgid: gid_t, egid: gid_t, sgid: gid_t) -> io::Result<()> {
+    match unsafe { libc::setresgid(rgid, egid, sgid) } {
+         0 => Ok(()),
+        -1 => Err(io::Error::last_os_error()),
+         n => unreachable!("setresgid returned {}", n)
+    }
+}
+
 /// Guard returned from a `switch_user_group` call.
+///
+/// Dropping the guard puts back the user and groups that were in effect
+/// before the switch. To find out whether that worked, call
+/// [`restore`](#method.restore) instead; otherwise, the guard’s
+/// [`DropPolicy`](enum.DropPolicy.html) decides what happens if it fails.
+#[must_use = "the previous user is restored as soon as the guard is dropped"]
 pub struct SwitchUserGuard {
     uid: uid_t,
     gid: gid_t,
+    groups: Option<Vec<gid_t>>,
+    policy: DropPolicy,
+    restored: bool,
+}
+
+/// What a `SwitchUserGuard` does when it is dropped and can’t put back the
+/// previous user or groups.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropPolicy {
+
+    /// Abort the process straight away. This is the safest choice, as the
+    /// process can’t carry on running as the wrong user, and it works even
+    /// if the guard is dropped while the thread is already panicking. With
+    /// the `logging` feature, the error is logged first.
+    Abort,
+
+    /// Panic, which is what guards have always done, and is the default.
+    /// If the guard is dropped while the thread is already panicking, a
+    /// second panic would abort the process without saying why, so this
+    /// behaves like `Abort` instead.
+    Panic,
+
+    /// Log the error and carry on, still running as the switched user.
+    /// Without the `logging` feature, this does nothing at all, so call
+    /// [`restore`](struct.SwitchUserGuard.html#method.restore) instead to
+    /// find out about the failure.
+    Log,
+}
+
+impl SwitchUserGuard {
+
+    /// Sets what happens when this guard is dropped and can’t put back the
+    /// previous user or groups.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use users::switch::{switch_user_group, DropPolicy};
+    ///
+    /// let guard = switch_user_group(1001, 1001).unwrap()
+    ///     .with_drop_policy(DropPolicy::Abort);
+    /// ```
+    pub fn with_drop_policy(mut self, policy: DropPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Puts back the user and groups that were in effect before the switch,
+    /// returning any error instead of following the drop policy.
+    ///
+    /// The effective group is restored first, then the effective user, then
+    /// the supplementary groups if they were switched. The first step that
+    /// fails stops the rest from being attempted.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` when an I/O error occurs during any
+    /// of the `setegid`, `seteuid`, or `setgroups` calls. The process may
+    /// then still be running as the switched user.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use users::switch::switch_user_group;
+    ///
+    /// let guard = switch_user_group(1001, 1001).unwrap();
+    /// // current and effective user and group IDs are 1001
+    /// if let Err(e) = guard.restore() {
+    ///     eprintln!("Failed to switch back: {}", e);
+    ///     std::process::exit(1);
+    /// }
+    /// ```
+    pub fn restore(mut self) -> io::Result<()> {
+        self.restored = true;
+        self.restore_previous()
+    }
+
+    fn restore_previous(&self) -> io::Result<()> {
+        set_effective_gid(self.gid)?;
+        set_effective_uid(self.uid)?;
+
+        // The supplementary groups can only be changed once the effective
+        // user has been set back.
+        if let Some(ref groups) = self.groups {
+            set_groups(groups)?;
+        }
+
+        Ok(())
+    }
 }
 
 impl Drop for SwitchUserGuard {
     fn drop(&mut self) {
-        set_effective_gid(self.gid).expect("Failed to set effective gid");
-        set_effective_uid(self.uid).expect("Failed to set effective uid");
+        if self.restored {
+            return;
+        }
+
+        if let Err(e) = self.restore_previous() {
+            self.policy.handle(&e);
+        }
     }
 }
 
+// Deriving `Default` for an enum needs Rust 1.62.
+#[allow(clippy::derivable_impls)]
+impl Default for DropPolicy {
+    fn default() -> Self {
+        DropPolicy::Panic
+    }
+}
+
+impl DropPolicy {
+
+    /// Acts on a guard’s failure to restore the previous state when it
+    /// was dropped.
+    fn handle(self, error: &io::Error) {
+        match self {
+            DropPolicy::Abort => {
+                report_restore_failure(error);
+                process::abort();
+            }
+            DropPolicy::Panic if std::thread::panicking() => {
+                report_restore_failure(error);
+                process::abort();
+            }
+            DropPolicy::Panic => {
+                panic!("Failed to restore user and groups: {}", error);
+            }
+            DropPolicy::Log => {
+                report_restore_failure(error);
+            }
+        }
+    }
+}
+
+/// Logs the failure, if the `logging` feature is on. This is a library, so
+/// it doesn’t print anything otherwise.
+pub(crate) fn report_restore_failure(error: &io::Error) {
+    #[cfg(feature = "logging")]
+    error!("Failed to restore user and groups: {}", error);
+
+    #[cfg(not(feature = "logging"))]
+    let _ = error;
+}
+
 /// Sets the **effective user** and the **effective group** for the current
 /// scope.
 ///
@@ -227,8 +454,12 @@ impl Drop for SwitchUserGuard {
 /// - This function switches the group before the user to prevent the user’s
 ///   privileges being dropped before trying to change the group (look up
 ///   `POS36-C`).
-/// - This function will panic upon failing to set either walue, so the
-///   program does not continue executing with too many privileges.
+/// - By default, dropping the guard will panic upon failing to set either
+///   value, so the program does not continue executing with too many
+///   privileges. Use [`SwitchUserGuard::restore`](struct.SwitchUserGuard.html#method.restore)
+///   to handle the error instead, or
+///   [`with_drop_policy`](struct.SwitchUserGuard.html#method.with_drop_policy)
+///   to choose what happens.
 ///
 /// # libc functions used
 ///
@@ -257,9 +488,366 @@ pub fn switch_user_group(uid: uid_t, gid: gid_t) -> io::Result<SwitchUserGuard>
     let current_state = SwitchUserGuard {
         gid: get_effective_gid(),
         uid: get_effective_uid(),
+        groups: None,
+        policy: DropPolicy::default(),
+        restored: false,
     };
 
     set_effective_gid(gid)?;
     set_effective_uid(uid)?;
     Ok(current_state)
 }
+
+/// Sets the **effective user**, the **effective group**, and the
+/// **supplementary groups** for the current scope.
+///
+/// This works like [`switch_user_group`](fn.switch_user_group.html), but
+/// also replaces the supplementary groups, so that permission checks made
+/// while the guard is alive don’t use the groups of the user that was
+/// running the process. The original groups are put back when the guard is
+/// dropped, after the effective user.
+///
+/// Changing the supplementary groups requires root privileges.
+///
+/// # Security considerations
+///
+/// - The supplementary groups are set first, then the group, then the user,
+///   as each step needs the privileges that the next one takes away.
+/// - If any step fails, the guard is dropped, putting back whatever had
+///   already been changed, before the error is returned.
+/// - Failing to restore any value on drop is handled by the guard’s
+///   [`DropPolicy`](enum.DropPolicy.html), like the guard returned from
+///   `switch_user_group`.
+///
+/// # libc functions used
+///
+/// - [`getgroups`](https://docs.rs/libc/*/libc/fn.getgroups.html)
+/// - [`setgroups`](https://docs.rs/libc/*/libc/fn.setgroups.html)
+/// - [`seteuid`](https://docs.rs/libc/*/libc/fn.seteuid.html)
+/// - [`setegid`](https://docs.rs/libc/*/libc/fn.setegid.html)
+///
+/// # Errors
+///
+/// This function will return `Err` when an I/O error occurs during any of
+/// the `getgroups`, `setgroups`, `seteuid`, or `setegid` calls.
+///
+/// # Examples
+///
+/// ```no_run
+/// use users::switch::switch_user_groups;
+///
+/// {
+///     let guard = switch_user_groups(1001, 1001, &[1001, 100]);
+///     // effective user and group IDs are 1001, and the process is only
+///     // in groups 1001 and 100
+///     drop(guard);
+/// }
+/// // back to the old values
+/// ```
+pub fn switch_user_groups(uid: uid_t, gid: gid_t, groups: &[gid_t]) -> io::Result<SwitchUserGuard> {
+    let current_state = SwitchUserGuard {
+        gid: get_effective_gid(),
+        uid: get_effective_uid(),
+        groups: Some(get_groups()?),
+        policy: DropPolicy::default(),
+        restored: false,
+    };
+
+    set_groups(groups)?;
+    set_effective_gid(gid)?;
+    set_effective_uid(uid)?;
+    Ok(current_state)
+}
+
+/// Sets the **effective user**, the **effective group**, and the
+/// **supplementary groups** to those of the given user for the current
+/// scope.
+///
+/// The supplementary groups are the ones
+/// [`get_user_groups`](../fn.get_user_groups.html) returns for the user.
+/// Otherwise, this works like
+/// [`switch_user_groups`](fn.switch_user_groups.html).
+///
+/// # Errors
+///
+/// This function will return `Err` when the user’s groups can’t be looked
+/// up, or when any of the calls made by `switch_user_groups` fail.
+///
+/// # Examples
+///
+/// ```no_run
+/// use users::get_user_by_name;
+/// use users::switch::switch_to_user;
+///
+/// let fred = get_user_by_name("fred").unwrap();
+/// {
+///     let guard = switch_to_user(&fred);
+///     // effective user, group, and supplementary groups are fred’s
+///     drop(guard);
+/// }
+/// // back to the old values
+/// ```
+pub fn switch_to_user(user: &User) -> io::Result<SwitchUserGuard> {
+    let gid = user.primary_group_id();
+    let groups = user_group_ids(user.name(), gid).ok_or_else(user_groups_error)?;
+    switch_user_groups(user.uid(), gid, &groups)
+}
+
+/// Permanently drops the running process’s privileges to those of the
+/// given user.
+///
+/// This is what a daemon that starts as root should call once it has done
+/// everything that needs root. It replaces the supplementary groups with
+/// the user’s own, then sets the real, effective, and saved group IDs to
+/// the user’s primary group, then sets the real, effective, and saved user
+/// IDs to the user’s ID. Finally, it checks that every one of those IDs
+/// changed, and that the process can’t switch back to root.
+///
+/// # Security considerations
+///
+/// - The groups are changed before the user, as changing the user first
+///   would take away the privileges needed to change the groups (look up
+///   `POS36-C`).
+/// - If this function returns an error, the process may have dropped some of
+///   its privileges but not others. Don’t carry on running: exit instead.
+/// - The last check tries to switch back to root. If that unexpectedly
+///   works, the process is running as root again, so rather than return an
+///   error that a caller could ignore, this function aborts the process.
+/// - On platforms without `setresuid` (macOS, NetBSD, and Solaris),
+///   `setgid` and `setuid` are used, which change the saved IDs as well when
+///   the process is running as root, and only the real and effective IDs
+///   are checked afterwards.
+///
+/// # libc functions used
+///
+/// - [`getgrouplist`](https://docs.rs/libc/*/libc/fn.getgrouplist.html)
+/// - [`setgroups`](https://docs.rs/libc/*/libc/fn.setgroups.html)
+/// - [`setresgid`](https://docs.rs/libc/*/libc/fn.setresgid.html)
+/// - [`setresuid`](https://docs.rs/libc/*/libc/fn.setresuid.html)
+/// - [`getresuid`](https://docs.rs/libc/*/libc/fn.getresuid.html)
+/// - [`getresgid`](https://docs.rs/libc/*/libc/fn.getresgid.html)
+/// - [`getgroups`](https://docs.rs/libc/*/libc/fn.getgroups.html)
+///
+/// # Errors
+///
+/// This function will return `Err` when the user’s groups can’t be looked
+/// up, when any of the calls fail, or when the checks afterwards find that
+/// an ID did not change or that root privileges can be regained.
+///
+/// # Examples
+///
+/// ```no_run
+/// use users::get_user_by_name;
+/// use users::switch::drop_privileges;
+///
+/// let nobody = get_user_by_name("nobody").unwrap();
+/// if let Err(e) = drop_privileges(&nobody) {
+///     eprintln!("Failed to drop privileges: {}", e);
+///     std::process::exit(1);
+/// }
+/// ```
+pub fn drop_privileges(user: &User) -> io::Result<()> {
+    let uid = user.uid();
+    let gid = user.primary_group_id();
+
+    let groups = user_group_ids(user.name(), gid).ok_or_else(user_groups_error)?;
+
+    set_groups(&groups)?;
+    set_all_gid(gid)?;
+    set_all_uid(uid)?;
+
+    verify_dropped(uid, gid, &groups)
+}
+
+fn user_groups_error() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "failed to look up the user’s groups")
+}
+
+/// Replaces the process’s supplementary groups.
+pub(crate) fn set_groups(groups: &[gid_t]) -> io::Result<()> {
+    #[allow(trivial_numeric_casts)]
+    let count = groups.len() as _;
+
+    match unsafe { libc::setgroups(count, groups.as_ptr()) } {
+         0 => Ok(()),
+        -1 => Err(io::Error::last_os_error()),
+         n => unreachable!("setgroups returned {}", n)
+    }
+}
+
+/// Returns the process’s supplementary groups.
+fn get_groups() -> io::Result<Vec<gid_t>> {
+    let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    if count < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut groups = vec![0; count as usize];
+    match unsafe { libc::getgroups(count, groups.as_mut_ptr()) } {
+        -1 => Err(io::Error::last_os_error()),
+         n => {
+            groups.truncate(n as usize);
+            Ok(groups)
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd", target_os = "dragonfly", target_os = "openbsd"))]
+pub(crate) fn set_all_uid(uid: uid_t) -> io::Result<()> {
+    set_res_uid(uid, uid, uid)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd", target_os = "dragonfly", target_os = "openbsd"))]
+pub(crate) fn set_all_gid(gid: gid_t) -> io::Result<()> {
+    set_res_gid(gid, gid, gid)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd", target_os = "dragonfly", target_os = "openbsd")))]
+pub(crate) fn set_all_uid(uid: uid_t) -> io::Result<()> {
+    set_current_uid(uid)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd", target_os = "dragonfly", target_os = "openbsd")))]
+pub(crate) fn set_all_gid(gid: gid_t) -> io::Result<()> {
+    set_current_gid(gid)
+}
+
+/// Returns the real, effective, and saved user IDs.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd", target_os = "dragonfly", target_os = "openbsd"))]
+fn get_all_uid() -> (uid_t, uid_t, uid_t) {
+    get_resuid()
+}
+
+/// Returns the real, effective, and saved group IDs.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd", target_os = "dragonfly", target_os = "openbsd"))]
+fn get_all_gid() -> (gid_t, gid_t, gid_t) {
+    get_resgid()
+}
+
+/// Returns the real and effective user IDs, twice over, as there is no way
+/// to read the saved one.
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd", target_os = "dragonfly", target_os = "openbsd")))]
+fn get_all_uid() -> (uid_t, uid_t, uid_t) {
+    let (ruid, euid) = (get_current_uid(), get_effective_uid());
+    (ruid, euid, euid)
+}
+
+/// Returns the real and effective group IDs, twice over, as there is no
+/// way to read the saved one.
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd", target_os = "dragonfly", target_os = "openbsd")))]
+fn get_all_gid() -> (gid_t, gid_t, gid_t) {
+    let (rgid, egid) = (get_current_gid(), get_effective_gid());
+    (rgid, egid, egid)
+}
+
+/// Checks that the process is now running entirely as the given user and
+/// groups, and that it can’t get root back.
+fn verify_dropped(uid: uid_t, gid: gid_t, groups: &[gid_t]) -> io::Result<()> {
+    if get_all_uid() != (uid, uid, uid) {
+        return Err(not_dropped("user IDs did not change"));
+    }
+
+    if get_all_gid() != (gid, gid, gid) {
+        return Err(not_dropped("group IDs did not change"));
+    }
+
+    let mut expected = groups.to_vec();
+    let mut actual = get_groups()?;
+    expected.sort_unstable();
+    expected.dedup();
+    actual.sort_unstable();
+    actual.dedup();
+
+    // Some platforms put the effective group into the list as well.
+    actual.retain(|&g| g != gid || expected.contains(&gid));
+    if actual != expected {
+        return Err(not_dropped("supplementary groups did not change"));
+    }
+
+    // If either of these works, the process has root back, and must not be
+    // allowed to carry on as if nothing happened.
+
+    if uid != 0 && (unsafe { libc::setuid(0) } == 0 || unsafe { libc::seteuid(0) } == 0) {
+        regained_root(&not_dropped("root user could be regained"));
+    }
+
+    if gid != 0 && (unsafe { libc::setgid(0) } == 0 || unsafe { libc::setegid(0) } == 0) {
+        regained_root(&not_dropped("root group could be regained"));
+    }
+
+    Ok(())
+}
+
+fn regained_root(error: &io::Error) -> ! {
+    #[cfg(feature = "logging")]
+    error!("{}", error);
+
+    #[cfg(not(feature = "logging"))]
+    let _ = error;
+
+    process::abort();
+}
+
+fn not_dropped(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, format!("privileges were not dropped: {}", message))
+}
+
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use std::sync::MutexGuard;
+    use base::StaticLock;
+
+    /// glibc changes every thread’s credentials whenever one thread switches
+    /// user, which would undo the changes that the per-thread tests make,
+    /// so those tests have to take turns.
+    pub(crate) fn lock() -> MutexGuard<'static, ()> {
+        static LOCK: StaticLock = StaticLock::new();
+        LOCK.lock()
+    }
+
+    #[test]
+    fn restore_to_same_user() {
+        let _lock = lock();
+
+        // Switching to the user that’s already in effect needs no privileges.
+        let guard = switch_user_group(get_effective_uid(), get_effective_gid()).unwrap();
+        guard.with_drop_policy(DropPolicy::Abort).restore().unwrap();
+    }
+
+    #[test]
+    fn drop_privileges_in_child() {
+        if get_effective_uid() != 0 {
+            return;
+        }
+
+        // There’s no getting root back afterwards, so the dropping happens
+        // in another copy of this test binary that only runs the test below.
+        let output = process::Command::new(::std::env::current_exe().unwrap())
+            .arg("--exact").arg("switch::test::drop_privileges_child")
+            .env(CHILD_VAR, "1")
+            .output()
+            .unwrap();
+
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+    }
+
+    const CHILD_VAR: &str = "USERS_TEST_DROP_PRIVILEGES";
+
+    #[test]
+    fn drop_privileges_child() {
+        if ::std::env::var_os(CHILD_VAR).is_none() {
+            return;
+        }
+
+        let user = User::new(65534, "users-test-nobody", 65534);
+        drop_privileges(&user).unwrap();
+
+        assert_eq!(get_all_uid(), (65534, 65534, 65534));
+        assert_eq!(get_all_gid(), (65534, 65534, 65534));
+        assert_eq!(get_groups().unwrap(), vec![65534]);
+        assert!(set_current_uid(0).is_err());
+        assert!(set_current_gid(0).is_err());
+    }
+}
@@ -0,0 +1,305 @@
+//! Switching the filesystem user and group of the current thread.
+//!
+//! On Linux, permission checks on files use the *filesystem* user and group
+//! IDs, which normally follow the effective IDs around. They can also be
+//! changed on their own with `setfsuid` and `setfsgid`, which only affect
+//! the calling thread, so a server can touch files as the user who asked
+//! for them without changing who the rest of the process runs as. Threads
+//! spawned while the IDs are switched start out with the switched IDs.
+//!
+//! `setfsuid` and `setfsgid` don’t report errors: they return the previous
+//! ID whether or not the change worked. The functions in this module check
+//! for themselves whether the ID actually changed, and return an error if
+//! it didn’t.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use std::fs::File;
+//! use users::switch::fs::switch_fs_user_group;
+//!
+//! let guard = switch_fs_user_group(1001, 1001).unwrap();
+//! // this thread creates files as user and group 1001
+//! let file = File::create("/srv/share/report.txt");
+//! drop(guard);
+//! ```
+
+use std::io;
+use std::marker::PhantomData;
+
+use libc::{uid_t, gid_t};
+
+use super::{DropPolicy, report_restore_failure};
+
+
+/// Sets the **filesystem user** for the current thread to the one with the
+/// given user ID, returning the previous one.
+///
+/// Typically, trying to switch to anyone other than the real, effective, or
+/// saved user requires root privileges.
+///
+/// # libc functions used
+///
+/// - [`setfsuid`](https://docs.rs/libc/*/libc/fn.setfsuid.html)
+///
+/// # Errors
+///
+/// This function will return `Err` with `EPERM` if the filesystem user did
+/// not change to the given one.
+///
+/// # Examples
+///
+/// ```no_run
+/// use users::switch::fs::set_fs_uid;
+///
+/// let previous = set_fs_uid(1001).unwrap();
+/// // filesystem user ID is 1001
+/// ```
+pub fn set_fs_uid(uid: uid_t) -> io::Result<uid_t> {
+    let previous = unsafe { libc::setfsuid(uid) } as uid_t;
+
+    if get_fs_uid() == uid { Ok(previous) }
+                      else { Err(io::Error::from_raw_os_error(libc::EPERM)) }
+}
+
+/// Sets the **filesystem group** for the current thread to the one with the
+/// given group ID, returning the previous one.
+///
+/// Typically, trying to switch to any group other than the real, effective,
+/// or saved group requires root privileges.
+///
+/// # libc functions used
+///
+/// - [`setfsgid`](https://docs.rs/libc/*/libc/fn.setfsgid.html)
+///
+/// # Errors
+///
+/// This function will return `Err` with `EPERM` if the filesystem group did
+/// not change to the given one.
+///
+/// # Examples
+///
+/// ```no_run
+/// use users::switch::fs::set_fs_gid;
+///
+/// let previous = set_fs_gid(1001).unwrap();
+/// // filesystem group ID is 1001
+/// ```
+pub fn set_fs_gid(gid: gid_t) -> io::Result<gid_t> {
+    let previous = unsafe { libc::setfsgid(gid) } as gid_t;
+
+    if get_fs_gid() == gid { Ok(previous) }
+                      else { Err(io::Error::from_raw_os_error(libc::EPERM)) }
+}
+
+/// Returns the **filesystem user** ID of the current thread.
+///
+/// # libc functions used
+///
+/// - [`setfsuid`](https://docs.rs/libc/*/libc/fn.setfsuid.html), with an
+///   invalid ID so that nothing changes
+///
+/// # Examples
+///
+/// ```
+/// use users::switch::fs::get_fs_uid;
+///
+/// println!("Files are accessed as user {}", get_fs_uid());
+/// ```
+pub fn get_fs_uid() -> uid_t {
+    unsafe { libc::setfsuid(!0) as uid_t }
+}
+
+/// Returns the **filesystem group** ID of the current thread.
+///
+/// # libc functions used
+///
+/// - [`setfsgid`](https://docs.rs/libc/*/libc/fn.setfsgid.html), with an
+///   invalid ID so that nothing changes
+///
+/// # Examples
+///
+/// ```
+/// use users::switch::fs::get_fs_gid;
+///
+/// println!("Files are accessed as group {}", get_fs_gid());
+/// ```
+pub fn get_fs_gid() -> gid_t {
+    unsafe { libc::setfsgid(!0) as gid_t }
+}
+
+
+/// Guard returned from a `switch_fs_user_group` call.
+///
+/// The filesystem IDs belong to the thread that changed them, so the guard
+/// can’t be sent to another thread, where dropping it would change the
+/// wrong thread’s IDs.
+#[must_use = "the previous filesystem user is restored as soon as the guard is dropped"]
+pub struct FsGuard {
+    uid: uid_t,
+    gid: gid_t,
+    policy: DropPolicy,
+    restored: bool,
+    _thread: PhantomData<*const ()>,
+}
+
+impl FsGuard {
+
+    /// Sets what happens when this guard is dropped and can’t put back the
+    /// previous filesystem user or group.
+    pub fn with_drop_policy(mut self, policy: DropPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Puts back the filesystem user and group that were in effect before
+    /// the switch, returning any error instead of following the drop
+    /// policy.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` if either ID could not be put back.
+    pub fn restore(mut self) -> io::Result<()> {
+        self.restored = true;
+        self.restore_previous()
+    }
+
+    fn restore_previous(&self) -> io::Result<()> {
+        set_fs_gid(self.gid)?;
+        set_fs_uid(self.uid)?;
+        Ok(())
+    }
+}
+
+impl Drop for FsGuard {
+    fn drop(&mut self) {
+        if self.restored {
+            return;
+        }
+
+        if let Err(e) = self.restore_previous() {
+            self.policy.handle(&e);
+        }
+    }
+}
+
+/// Sets the **filesystem user** and the **filesystem group** of the current
+/// thread for the current scope.
+///
+/// The group is switched before the user, and they are put back in the
+/// same order when the guard is dropped. If switching the user fails, the
+/// group is put back before the error is returned, whatever the drop
+/// policy. If putting it back fails too, that failure is logged, and the
+/// original error is still the one returned.
+///
+/// # libc functions used
+///
+/// - [`setfsuid`](https://docs.rs/libc/*/libc/fn.setfsuid.html)
+/// - [`setfsgid`](https://docs.rs/libc/*/libc/fn.setfsgid.html)
+///
+/// # Errors
+///
+/// This function will return `Err` with `EPERM` if either ID did not
+/// change.
+///
+/// # Examples
+///
+/// ```no_run
+/// use users::switch::fs::switch_fs_user_group;
+///
+/// {
+///     let guard = switch_fs_user_group(1001, 1001);
+///     // filesystem user and group IDs are 1001 on this thread only
+///     drop(guard);
+/// }
+/// // back to the old values
+/// ```
+pub fn switch_fs_user_group(uid: uid_t, gid: gid_t) -> io::Result<FsGuard> {
+    let mut current_state = FsGuard {
+        uid: get_fs_uid(),
+        gid: get_fs_gid(),
+        policy: DropPolicy::default(),
+        restored: false,
+        _thread: PhantomData,
+    };
+
+    if let Err(e) = set_fs_gid(gid).and_then(|_| set_fs_uid(uid)) {
+        // Put back whatever did change here, instead of leaving it to the
+        // guard, so the caller gets this error rather than the drop policy.
+        current_state.restored = true;
+        if let Err(restore_error) = current_state.restore_previous() {
+            report_restore_failure(&restore_error);
+        }
+
+        return Err(e);
+    }
+
+    Ok(current_state)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+
+    #[test]
+    fn same_ids() {
+        let _lock = ::switch::test::lock();
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        assert_eq!(get_fs_uid(), uid);
+        assert_eq!(get_fs_gid(), gid);
+
+        let guard = switch_fs_user_group(uid, gid).unwrap();
+        guard.restore().unwrap();
+    }
+
+    #[test]
+    fn only_this_thread() {
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+
+        // Switching on a thread of our own leaves the other tests alone.
+        let _lock = ::switch::test::lock();
+        let (switched_tx, switched_rx) = mpsc::channel();
+        let (checked_tx, checked_rx) = mpsc::channel();
+
+        let switcher = thread::spawn(move || {
+            let guard = switch_fs_user_group(65534, 65534).unwrap();
+            assert_eq!(get_fs_uid(), 65534);
+            assert_eq!(get_fs_gid(), 65534);
+
+            switched_tx.send(()).unwrap();
+            checked_rx.recv().unwrap();
+
+            drop(guard);
+            assert_eq!(get_fs_uid(), 0);
+        });
+
+        switched_rx.recv().unwrap();
+        assert_eq!(get_fs_uid(), 0);
+        checked_tx.send(()).unwrap();
+        switcher.join().unwrap();
+    }
+
+    #[test]
+    fn user_failure_puts_group_back() {
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+
+        // Without root as the effective user, the filesystem group can
+        // still become the real group, 0, but the user can’t become 1.
+        let _lock = ::switch::test::lock();
+        thread::spawn(|| {
+            let _nobody = ::switch::thread::switch_thread_user_group(65534, 65534).unwrap();
+
+            let error = switch_fs_user_group(1, 0).err().unwrap();
+            assert_eq!(error.raw_os_error(), Some(libc::EPERM));
+            assert_eq!(get_fs_uid(), 65534);
+            assert_eq!(get_fs_gid(), 65534);
+        }).join().unwrap();
+    }
+}
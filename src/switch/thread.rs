@@ -0,0 +1,333 @@
+//! Switching the user and groups of the current thread only.
+//!
+//! The kernel keeps a separate set of credentials for every thread, but
+//! POSIX says that a process has one user, so glibc’s `seteuid` and friends
+//! change every thread in the process one after another. That makes
+//! [`switch_user_group`](../fn.switch_user_group.html) unusable in a
+//! multi-threaded program, such as one built on an async runtime, where other
+//! threads would briefly run as the wrong user.
+//!
+//! This module calls the `setresuid`, `setresgid`, and `setgroups` system
+//! calls directly, bypassing glibc, so only the calling thread changes.
+//! The guards can’t be sent to another thread, where dropping them would
+//! change the wrong thread’s credentials.
+//!
+//! ## Caveats
+//!
+//! - If any thread in the process calls one of glibc’s `set*id` functions
+//!   while a thread has switched with this module, glibc will overwrite that
+//!   thread’s credentials too.
+//! - Threads spawned while the credentials are switched start out with the
+//!   switched credentials.
+//! - The C library’s `getuid`, `geteuid`, `getgroups`, and similar functions
+//!   ask the kernel directly, so they report the calling thread’s
+//!   credentials, including any changes made here.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use users::switch::thread::switch_thread_user_group;
+//!
+//! let guard = switch_thread_user_group(1001, 1001).unwrap();
+//! // this thread runs as user and group 1001; the others don’t
+//! drop(guard);
+//! ```
+
+use std::io;
+use std::marker::PhantomData;
+
+use libc::{uid_t, gid_t, c_long};
+
+use base::{get_resuid, get_resgid};
+use super::{DropPolicy, get_groups, report_restore_failure};
+
+// 32-bit x86, ARM, and SPARC kept the original system calls for 16-bit IDs,
+// and added new ones for 32-bit IDs.
+#[cfg(any(target_arch = "x86", target_arch = "arm", target_arch = "sparc"))]
+use libc::{SYS_setresuid32 as SYS_SETRESUID, SYS_setresgid32 as SYS_SETRESGID, SYS_setgroups32 as SYS_SETGROUPS};
+#[cfg(not(any(target_arch = "x86", target_arch = "arm", target_arch = "sparc")))]
+use libc::{SYS_setresuid as SYS_SETRESUID, SYS_setresgid as SYS_SETRESGID, SYS_setgroups as SYS_SETGROUPS};
+
+
+/// An ID that leaves the corresponding value unchanged.
+const UNCHANGED: uid_t = !0;
+
+
+/// Sets the **current user**, the **effective user**, and the **saved
+/// set-user-ID** of the calling thread only. Pass `!0` for any of
+/// them to leave it unchanged.
+///
+/// # System calls used
+///
+/// - `setresuid`
+///
+/// # Errors
+///
+/// This function will return `Err` when the system call fails.
+///
+/// # Examples
+///
+/// ```no_run
+/// use users::switch::thread::set_thread_res_uid;
+///
+/// set_thread_res_uid(!0, 1001, !0);
+/// // effective user ID of this thread is 1001
+/// ```
+pub fn set_thread_res_uid(ruid: uid_t, euid: uid_t, suid: uid_t) -> io::Result<()> {
+    match unsafe { libc::syscall(SYS_SETRESUID, ruid, euid, suid) } {
+         0 => Ok(()),
+        -1 => Err(io::Error::last_os_error()),
+         n => unreachable!("setresuid returned {}", n)
+    }
+}
+
+/// Sets the **current group**, the **effective group**, and the **saved
+/// set-group-ID** of the calling thread only. Pass `!0` for any of
+/// them to leave it unchanged.
+///
+/// # System calls used
+///
+/// - `setresgid`
+///
+/// # Errors
+///
+/// This function will return `Err` when the system call fails.
+///
+/// # Examples
+///
+/// ```no_run
+/// use users::switch::thread::set_thread_res_gid;
+///
+/// set_thread_res_gid(!0, 1001, !0);
+/// // effective group ID of this thread is 1001
+/// ```
+pub fn set_thread_res_gid(rgid: gid_t, egid: gid_t, sgid: gid_t) -> io::Result<()> {
+    match unsafe { libc::syscall(SYS_SETRESGID, rgid, egid, sgid) } {
+         0 => Ok(()),
+        -1 => Err(io::Error::last_os_error()),
+         n => unreachable!("setresgid returned {}", n)
+    }
+}
+
+/// Sets the **supplementary groups** of the calling thread only.
+///
+/// # System calls used
+///
+/// - `setgroups`
+///
+/// # Errors
+///
+/// This function will return `Err` when the system call fails.
+///
+/// # Examples
+///
+/// ```no_run
+/// use users::switch::thread::set_thread_groups;
+///
+/// set_thread_groups(&[1001, 100]);
+/// // this thread is only in groups 1001 and 100
+/// ```
+pub fn set_thread_groups(groups: &[gid_t]) -> io::Result<()> {
+    match unsafe { libc::syscall(SYS_SETGROUPS, groups.len() as c_long, groups.as_ptr()) } {
+         0 => Ok(()),
+        -1 => Err(io::Error::last_os_error()),
+         n => unreachable!("setgroups returned {}", n)
+    }
+}
+
+
+/// Guard returned from a `switch_thread_user_group` or
+/// `switch_thread_user_groups` call.
+#[must_use = "the thread’s previous user is restored as soon as the guard is dropped"]
+pub struct ThreadGuard {
+    uid: uid_t,
+    gid: gid_t,
+    groups: Option<Vec<gid_t>>,
+    policy: DropPolicy,
+    restored: bool,
+    _thread: PhantomData<*const ()>,
+}
+
+impl ThreadGuard {
+
+    /// Sets what happens when this guard is dropped and can’t put back the
+    /// thread’s previous user or groups.
+    pub fn with_drop_policy(mut self, policy: DropPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Puts back the thread’s user and groups from before the switch,
+    /// returning any error instead of following the drop policy.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` when any of the system calls fail.
+    /// The thread may then still be running as the switched user.
+    pub fn restore(mut self) -> io::Result<()> {
+        self.restored = true;
+        self.restore_previous()
+    }
+
+    fn restore_previous(&self) -> io::Result<()> {
+        set_thread_res_gid(UNCHANGED, self.gid, UNCHANGED)?;
+        set_thread_res_uid(UNCHANGED, self.uid, UNCHANGED)?;
+
+        // The supplementary groups can only be changed once the effective
+        // user has been set back.
+        if let Some(ref groups) = self.groups {
+            set_thread_groups(groups)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for ThreadGuard {
+    fn drop(&mut self) {
+        if self.restored {
+            return;
+        }
+
+        if let Err(e) = self.restore_previous() {
+            self.policy.handle(&e);
+        }
+    }
+}
+
+/// Sets the **effective user** and the **effective group** of the calling
+/// thread for the current scope.
+///
+/// This works like [`switch_user_group`](../fn.switch_user_group.html), but
+/// leaves the process’s other threads alone.
+///
+/// # Errors
+///
+/// This function will return `Err` when either system call fails. The
+/// group is put back first if it had already been switched, whatever the
+/// guard’s drop policy.
+///
+/// # Examples
+///
+/// ```no_run
+/// use users::switch::thread::switch_thread_user_group;
+///
+/// {
+///     let guard = switch_thread_user_group(1001, 1001);
+///     // effective user and group IDs of this thread are 1001
+///     drop(guard);
+/// }
+/// // back to the old values
+/// ```
+pub fn switch_thread_user_group(uid: uid_t, gid: gid_t) -> io::Result<ThreadGuard> {
+    switch_thread(uid, gid, None)
+}
+
+/// Sets the **effective user**, the **effective group**, and the
+/// **supplementary groups** of the calling thread for the current scope.
+///
+/// This works like [`switch_user_groups`](../fn.switch_user_groups.html),
+/// but leaves the process’s other threads alone.
+///
+/// # Errors
+///
+/// This function will return `Err` when the thread’s groups can’t be read,
+/// or when any of the system calls fail. Anything that had already been
+/// switched is put back first, whatever the guard’s drop policy.
+///
+/// # Examples
+///
+/// ```no_run
+/// use users::switch::thread::switch_thread_user_groups;
+///
+/// {
+///     let guard = switch_thread_user_groups(1001, 1001, &[1001, 100]);
+///     // this thread runs as user 1001, in groups 1001 and 100
+///     drop(guard);
+/// }
+/// // back to the old values
+/// ```
+pub fn switch_thread_user_groups(uid: uid_t, gid: gid_t, groups: &[gid_t]) -> io::Result<ThreadGuard> {
+    switch_thread(uid, gid, Some(groups))
+}
+
+fn switch_thread(uid: uid_t, gid: gid_t, groups: Option<&[gid_t]>) -> io::Result<ThreadGuard> {
+    let mut current_state = ThreadGuard {
+        uid: get_resuid().1,
+        gid: get_resgid().1,
+        groups: match groups {
+            Some(_) => Some(get_groups()?),
+            None    => None,
+        },
+        policy: DropPolicy::default(),
+        restored: false,
+        _thread: PhantomData,
+    };
+
+    let switched = match groups {
+        Some(groups) => set_thread_groups(groups),
+        None         => Ok(()),
+    };
+
+    let switched = switched.and_then(|_| set_thread_res_gid(UNCHANGED, gid, UNCHANGED))
+                           .and_then(|_| set_thread_res_uid(UNCHANGED, uid, UNCHANGED));
+
+    if let Err(e) = switched {
+        // Put back whatever did change here, instead of leaving it to the
+        // guard, so the caller gets this error rather than the drop policy.
+        current_state.restored = true;
+        if let Err(restore_error) = current_state.restore_previous() {
+            report_restore_failure(&restore_error);
+        }
+
+        return Err(e);
+    }
+
+    Ok(current_state)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+
+    #[test]
+    fn same_ids() {
+        let _lock = ::switch::test::lock();
+
+        let guard = switch_thread_user_group(get_resuid().1, get_resgid().1).unwrap();
+        guard.restore().unwrap();
+    }
+
+    #[test]
+    fn only_this_thread() {
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+
+        let _lock = ::switch::test::lock();
+        let (switched_tx, switched_rx) = mpsc::channel();
+        let (checked_tx, checked_rx) = mpsc::channel();
+
+        let switcher = thread::spawn(move || {
+            let guard = switch_thread_user_groups(65534, 65534, &[65534]).unwrap();
+            assert_eq!(get_resuid(), (0, 65534, 0));
+            assert_eq!(get_resgid(), (0, 65534, 0));
+            assert_eq!(get_groups().unwrap(), vec![65534]);
+
+            switched_tx.send(()).unwrap();
+            checked_rx.recv().unwrap();
+
+            drop(guard);
+            assert_eq!(get_resuid(), (0, 0, 0));
+        });
+
+        switched_rx.recv().unwrap();
+        assert_eq!(get_resuid(), (0, 0, 0));
+        assert_ne!(get_groups().unwrap(), vec![65534]);
+        checked_tx.send(()).unwrap();
+        switcher.join().unwrap();
+    }
+}
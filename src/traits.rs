@@ -3,7 +3,7 @@ use std::sync::Arc;
 
 use libc::{uid_t, gid_t};
 
-use base::{User, Group};
+use base::{User, Group, LookupError};
 
 
 /// Trait for producers of users.
@@ -15,6 +15,22 @@ pub trait Users {
     /// Returns a `User` if one exists for the given username; otherwise, returns `None`.
     fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Option<Arc<User>>;
 
+    /// Returns a `User` if one exists for the given user ID, `None` if one
+    /// does not, or an error if the lookup itself failed.
+    ///
+    /// The default implementation never fails.
+    fn try_get_user_by_uid(&self, uid: uid_t) -> Result<Option<Arc<User>>, LookupError> {
+        Ok(self.get_user_by_uid(uid))
+    }
+
+    /// Returns a `User` if one exists for the given username, `None` if one
+    /// does not, or an error if the lookup itself failed.
+    ///
+    /// The default implementation never fails.
+    fn try_get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Result<Option<Arc<User>>, LookupError> {
+        Ok(self.get_user_by_name(username))
+    }
+
     /// Returns the user ID for the user running the process.
     fn get_current_uid(&self) -> uid_t;
 
@@ -37,6 +53,22 @@ pub trait Groups {
     /// Returns a `Group` if one exists for the given groupname; otherwise, returns `None`.
     fn get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Option<Arc<Group>>;
 
+    /// Returns a `Group` if one exists for the given group ID, `None` if one
+    /// does not, or an error if the lookup itself failed.
+    ///
+    /// The default implementation never fails.
+    fn try_get_group_by_gid(&self, gid: gid_t) -> Result<Option<Arc<Group>>, LookupError> {
+        Ok(self.get_group_by_gid(gid))
+    }
+
+    /// Returns a `Group` if one exists for the given groupname, `None` if one
+    /// does not, or an error if the lookup itself failed.
+    ///
+    /// The default implementation never fails.
+    fn try_get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Result<Option<Arc<Group>>, LookupError> {
+        Ok(self.get_group_by_name(group_name))
+    }
+
     /// Returns the group ID for the user running the process.
     fn get_current_gid(&self) -> gid_t;
 
@@ -48,4 +80,415 @@ pub trait Groups {
 
     /// Returns the effective group name.
     fn get_effective_groupname(&self) -> Option<Arc<OsStr>>;
+
+    /// Returns the groups that the user with the given name is a member
+    /// of, along with the group with the given ID, which should be their
+    /// primary group. Returns `None` if the list can’t be read.
+    ///
+    /// The default implementation only knows about the primary group, and
+    /// returns just the group with the given ID, if it exists. Implementors
+    /// that can list a user’s supplementary groups should override it.
+    fn get_user_groups<S: AsRef<OsStr> + ?Sized>(&self, username: &S, gid: gid_t) -> Option<Vec<Arc<Group>>> {
+        let _ = username;
+        Some(self.get_group_by_gid(gid).into_iter().collect())
+    }
+
+    /// Returns whether the given user is a member of the group with the
+    /// given ID, either as their primary group or a supplementary one.
+    ///
+    /// The default implementation searches the result of
+    /// `get_user_groups`.
+    fn is_member(&self, user: &User, gid: gid_t) -> bool {
+        if user.primary_group_id() == gid {
+            return true;
+        }
+
+        match self.get_user_groups(user.name(), user.primary_group_id()) {
+            Some(groups) => groups.iter().any(|g| g.gid() == gid),
+            None         => false,
+        }
+    }
+}
+
+
+/// An object-safe version of [`Users`](trait.Users.html), for choosing a
+/// source of users at runtime.
+///
+/// The methods of `Users` that take names are generic, so there can’t be a
+/// `dyn Users`. This trait takes `&OsStr` instead, and is implemented for
+/// every type that implements `Users`. In turn, `dyn DynUsers` and
+/// `Box<dyn DynUsers>` implement `Users`, so they can be passed to code
+/// that is generic over it.
+///
+/// Its methods have a `dyn_` prefix, so that they don’t clash with the
+/// ones from `Users` when both traits are in scope. Code holding a
+/// `Box<dyn DynUsers>` can call either.
+///
+/// # Examples
+///
+/// ```
+/// use users::{Users, DynUsers, UsersCache};
+/// use users::mock::MockUsers;
+///
+/// fn users_for(testing: bool) -> Box<dyn DynUsers> {
+///     if testing { Box::new(MockUsers::with_current_uid(1000)) }
+///           else { Box::new(UsersCache::new()) }
+/// }
+///
+/// let users = users_for(true);
+/// assert_eq!(users.get_current_uid(), 1000);
+/// ```
+pub trait DynUsers {
+
+    /// Returns a `User` if one exists for the given user ID; otherwise, returns `None`.
+    fn dyn_get_user_by_uid(&self, uid: uid_t) -> Option<Arc<User>>;
+
+    /// Returns a `User` if one exists for the given username; otherwise, returns `None`.
+    fn dyn_get_user_by_name(&self, username: &OsStr) -> Option<Arc<User>>;
+
+    /// Returns a `User` if one exists for the given user ID, `None` if one
+    /// does not, or an error if the lookup itself failed.
+    fn dyn_try_get_user_by_uid(&self, uid: uid_t) -> Result<Option<Arc<User>>, LookupError>;
+
+    /// Returns a `User` if one exists for the given username, `None` if one
+    /// does not, or an error if the lookup itself failed.
+    fn dyn_try_get_user_by_name(&self, username: &OsStr) -> Result<Option<Arc<User>>, LookupError>;
+
+    /// Returns the user ID for the user running the process.
+    fn dyn_get_current_uid(&self) -> uid_t;
+
+    /// Returns the username of the user running the process.
+    fn dyn_get_current_username(&self) -> Option<Arc<OsStr>>;
+
+    /// Returns the effective user id.
+    fn dyn_get_effective_uid(&self) -> uid_t;
+
+    /// Returns the effective username.
+    fn dyn_get_effective_username(&self) -> Option<Arc<OsStr>>;
+}
+
+/// An object-safe version of [`Groups`](trait.Groups.html), for choosing a
+/// source of groups at runtime. It works in the same way as
+/// [`DynUsers`](trait.DynUsers.html).
+pub trait DynGroups {
+
+    /// Returns a `Group` if one exists for the given group ID; otherwise, returns `None`.
+    fn dyn_get_group_by_gid(&self, gid: gid_t) -> Option<Arc<Group>>;
+
+    /// Returns a `Group` if one exists for the given groupname; otherwise, returns `None`.
+    fn dyn_get_group_by_name(&self, group_name: &OsStr) -> Option<Arc<Group>>;
+
+    /// Returns a `Group` if one exists for the given group ID, `None` if one
+    /// does not, or an error if the lookup itself failed.
+    fn dyn_try_get_group_by_gid(&self, gid: gid_t) -> Result<Option<Arc<Group>>, LookupError>;
+
+    /// Returns a `Group` if one exists for the given groupname, `None` if one
+    /// does not, or an error if the lookup itself failed.
+    fn dyn_try_get_group_by_name(&self, group_name: &OsStr) -> Result<Option<Arc<Group>>, LookupError>;
+
+    /// Returns the group ID for the user running the process.
+    fn dyn_get_current_gid(&self) -> gid_t;
+
+    /// Returns the group name of the user running the process.
+    fn dyn_get_current_groupname(&self) -> Option<Arc<OsStr>>;
+
+    /// Returns the effective group id.
+    fn dyn_get_effective_gid(&self) -> gid_t;
+
+    /// Returns the effective group name.
+    fn dyn_get_effective_groupname(&self) -> Option<Arc<OsStr>>;
+
+    /// Returns the groups that the user with the given name is a member
+    /// of, along with the group with the given ID.
+    fn dyn_get_user_groups(&self, username: &OsStr, gid: gid_t) -> Option<Vec<Arc<Group>>>;
+
+    /// Returns whether the given user is a member of the group with the
+    /// given ID.
+    fn dyn_is_member(&self, user: &User, gid: gid_t) -> bool;
+}
+
+
+impl<U: Users> DynUsers for U {
+    fn dyn_get_user_by_uid(&self, uid: uid_t) -> Option<Arc<User>> {
+        Users::get_user_by_uid(self, uid)
+    }
+
+    fn dyn_get_user_by_name(&self, username: &OsStr) -> Option<Arc<User>> {
+        Users::get_user_by_name(self, username)
+    }
+
+    fn dyn_try_get_user_by_uid(&self, uid: uid_t) -> Result<Option<Arc<User>>, LookupError> {
+        Users::try_get_user_by_uid(self, uid)
+    }
+
+    fn dyn_try_get_user_by_name(&self, username: &OsStr) -> Result<Option<Arc<User>>, LookupError> {
+        Users::try_get_user_by_name(self, username)
+    }
+
+    fn dyn_get_current_uid(&self) -> uid_t {
+        Users::get_current_uid(self)
+    }
+
+    fn dyn_get_current_username(&self) -> Option<Arc<OsStr>> {
+        Users::get_current_username(self)
+    }
+
+    fn dyn_get_effective_uid(&self) -> uid_t {
+        Users::get_effective_uid(self)
+    }
+
+    fn dyn_get_effective_username(&self) -> Option<Arc<OsStr>> {
+        Users::get_effective_username(self)
+    }
+}
+
+impl<G: Groups> DynGroups for G {
+    fn dyn_get_group_by_gid(&self, gid: gid_t) -> Option<Arc<Group>> {
+        Groups::get_group_by_gid(self, gid)
+    }
+
+    fn dyn_get_group_by_name(&self, group_name: &OsStr) -> Option<Arc<Group>> {
+        Groups::get_group_by_name(self, group_name)
+    }
+
+    fn dyn_try_get_group_by_gid(&self, gid: gid_t) -> Result<Option<Arc<Group>>, LookupError> {
+        Groups::try_get_group_by_gid(self, gid)
+    }
+
+    fn dyn_try_get_group_by_name(&self, group_name: &OsStr) -> Result<Option<Arc<Group>>, LookupError> {
+        Groups::try_get_group_by_name(self, group_name)
+    }
+
+    fn dyn_get_current_gid(&self) -> gid_t {
+        Groups::get_current_gid(self)
+    }
+
+    fn dyn_get_current_groupname(&self) -> Option<Arc<OsStr>> {
+        Groups::get_current_groupname(self)
+    }
+
+    fn dyn_get_effective_gid(&self) -> gid_t {
+        Groups::get_effective_gid(self)
+    }
+
+    fn dyn_get_effective_groupname(&self) -> Option<Arc<OsStr>> {
+        Groups::get_effective_groupname(self)
+    }
+
+    fn dyn_get_user_groups(&self, username: &OsStr, gid: gid_t) -> Option<Vec<Arc<Group>>> {
+        Groups::get_user_groups(self, username, gid)
+    }
+
+    fn dyn_is_member(&self, user: &User, gid: gid_t) -> bool {
+        Groups::is_member(self, user, gid)
+    }
+}
+
+
+// Trait objects with different auto traits are different types, so each
+// one that is likely to be boxed up needs its own impl.
+
+macro_rules! impl_users_for_dyn {
+    ($($ty:ty),*) => { $(
+        impl<'a> Users for $ty {
+            fn get_user_by_uid(&self, uid: uid_t) -> Option<Arc<User>> {
+                DynUsers::dyn_get_user_by_uid(self, uid)
+            }
+
+            fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Option<Arc<User>> {
+                DynUsers::dyn_get_user_by_name(self, username.as_ref())
+            }
+
+            fn try_get_user_by_uid(&self, uid: uid_t) -> Result<Option<Arc<User>>, LookupError> {
+                DynUsers::dyn_try_get_user_by_uid(self, uid)
+            }
+
+            fn try_get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Result<Option<Arc<User>>, LookupError> {
+                DynUsers::dyn_try_get_user_by_name(self, username.as_ref())
+            }
+
+            fn get_current_uid(&self) -> uid_t {
+                DynUsers::dyn_get_current_uid(self)
+            }
+
+            fn get_current_username(&self) -> Option<Arc<OsStr>> {
+                DynUsers::dyn_get_current_username(self)
+            }
+
+            fn get_effective_uid(&self) -> uid_t {
+                DynUsers::dyn_get_effective_uid(self)
+            }
+
+            fn get_effective_username(&self) -> Option<Arc<OsStr>> {
+                DynUsers::dyn_get_effective_username(self)
+            }
+        }
+    )* };
+}
+
+macro_rules! impl_groups_for_dyn {
+    ($($ty:ty),*) => { $(
+        impl<'a> Groups for $ty {
+            fn get_group_by_gid(&self, gid: gid_t) -> Option<Arc<Group>> {
+                DynGroups::dyn_get_group_by_gid(self, gid)
+            }
+
+            fn get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Option<Arc<Group>> {
+                DynGroups::dyn_get_group_by_name(self, group_name.as_ref())
+            }
+
+            fn try_get_group_by_gid(&self, gid: gid_t) -> Result<Option<Arc<Group>>, LookupError> {
+                DynGroups::dyn_try_get_group_by_gid(self, gid)
+            }
+
+            fn try_get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Result<Option<Arc<Group>>, LookupError> {
+                DynGroups::dyn_try_get_group_by_name(self, group_name.as_ref())
+            }
+
+            fn get_current_gid(&self) -> gid_t {
+                DynGroups::dyn_get_current_gid(self)
+            }
+
+            fn get_current_groupname(&self) -> Option<Arc<OsStr>> {
+                DynGroups::dyn_get_current_groupname(self)
+            }
+
+            fn get_effective_gid(&self) -> gid_t {
+                DynGroups::dyn_get_effective_gid(self)
+            }
+
+            fn get_effective_groupname(&self) -> Option<Arc<OsStr>> {
+                DynGroups::dyn_get_effective_groupname(self)
+            }
+
+            fn get_user_groups<S: AsRef<OsStr> + ?Sized>(&self, username: &S, gid: gid_t) -> Option<Vec<Arc<Group>>> {
+                DynGroups::dyn_get_user_groups(self, username.as_ref(), gid)
+            }
+
+            fn is_member(&self, user: &User, gid: gid_t) -> bool {
+                DynGroups::dyn_is_member(self, user, gid)
+            }
+        }
+    )* };
+}
+
+impl_users_for_dyn!(dyn DynUsers + 'a, dyn DynUsers + Send + 'a, dyn DynUsers + Sync + 'a, dyn DynUsers + Send + Sync + 'a);
+impl_groups_for_dyn!(dyn DynGroups + 'a, dyn DynGroups + Send + 'a, dyn DynGroups + Sync + 'a, dyn DynGroups + Send + Sync + 'a);
+
+impl<U: Users + ?Sized> Users for Box<U> {
+    fn get_user_by_uid(&self, uid: uid_t) -> Option<Arc<User>> {
+        (**self).get_user_by_uid(uid)
+    }
+
+    fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Option<Arc<User>> {
+        (**self).get_user_by_name(username)
+    }
+
+    fn try_get_user_by_uid(&self, uid: uid_t) -> Result<Option<Arc<User>>, LookupError> {
+        (**self).try_get_user_by_uid(uid)
+    }
+
+    fn try_get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Result<Option<Arc<User>>, LookupError> {
+        (**self).try_get_user_by_name(username)
+    }
+
+    fn get_current_uid(&self) -> uid_t {
+        (**self).get_current_uid()
+    }
+
+    fn get_current_username(&self) -> Option<Arc<OsStr>> {
+        (**self).get_current_username()
+    }
+
+    fn get_effective_uid(&self) -> uid_t {
+        (**self).get_effective_uid()
+    }
+
+    fn get_effective_username(&self) -> Option<Arc<OsStr>> {
+        (**self).get_effective_username()
+    }
+}
+
+impl<G: Groups + ?Sized> Groups for Box<G> {
+    fn get_group_by_gid(&self, gid: gid_t) -> Option<Arc<Group>> {
+        (**self).get_group_by_gid(gid)
+    }
+
+    fn get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Option<Arc<Group>> {
+        (**self).get_group_by_name(group_name)
+    }
+
+    fn try_get_group_by_gid(&self, gid: gid_t) -> Result<Option<Arc<Group>>, LookupError> {
+        (**self).try_get_group_by_gid(gid)
+    }
+
+    fn try_get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Result<Option<Arc<Group>>, LookupError> {
+        (**self).try_get_group_by_name(group_name)
+    }
+
+    fn get_current_gid(&self) -> gid_t {
+        (**self).get_current_gid()
+    }
+
+    fn get_current_groupname(&self) -> Option<Arc<OsStr>> {
+        (**self).get_current_groupname()
+    }
+
+    fn get_effective_gid(&self) -> gid_t {
+        (**self).get_effective_gid()
+    }
+
+    fn get_effective_groupname(&self) -> Option<Arc<OsStr>> {
+        (**self).get_effective_groupname()
+    }
+
+    fn get_user_groups<S: AsRef<OsStr> + ?Sized>(&self, username: &S, gid: gid_t) -> Option<Vec<Arc<Group>>> {
+        (**self).get_user_groups(username, gid)
+    }
+
+    fn is_member(&self, user: &User, gid: gid_t) -> bool {
+        (**self).is_member(user, gid)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{Users, Groups, DynUsers, DynGroups};
+    use base::User;
+    use std::ffi::OsStr;
+    use std::sync::Arc;
+    use files::RootUsers;
+    use test_util::TempDir;
+
+    fn current_username<U: Users + ?Sized>(users: &U) -> Option<Arc<OsStr>> {
+        users.get_current_username()
+    }
+
+    #[test]
+    fn boxed_users() {
+        let root = TempDir::new("boxed_users");
+        root.write("etc/passwd", "root:x:0:0::/root:/bin/sh\nfred:x:1000:100::/home/fred:/bin/sh\n");
+
+        let users: Box<dyn DynUsers + Send + Sync> = Box::new(RootUsers::open(root.path()).unwrap());
+        assert_eq!(users.get_user_by_name("fred").map(|u| u.uid()), Some(1000));
+        assert_eq!(users.dyn_get_user_by_name(OsStr::new("root")).map(|u| u.uid()), Some(0));
+        assert_eq!(current_username(&users), current_username(&*users));
+
+        let users: Box<dyn DynUsers + Sync> = Box::new(RootUsers::open(root.path()).unwrap());
+        assert_eq!(users.get_user_by_uid(1000).map(|u| u.uid()), Some(1000));
+    }
+
+    #[test]
+    fn boxed_groups() {
+        // An existing root without an `etc/group` file has no groups.
+        let root = TempDir::new("boxed_groups");
+
+        let groups: Box<dyn DynGroups> = Box::new(RootUsers::open(root.path()).unwrap());
+        assert!(groups.get_group_by_name("root").is_none());
+
+        let groups: Box<dyn DynGroups + Sync> = Box::new(RootUsers::open(root.path()).unwrap());
+        assert_eq!(groups.get_user_groups("root", 0).map(|g| g.len()), Some(0));
+        assert!(groups.is_member(&User::new(0, "root", 0), 0));
+    }
 }